@@ -1,11 +1,47 @@
 use bevy::{math::Vec3Swizzles, prelude::*};
 
 use crate::{
+    composite::Composite,
+    file::{aseprite::load_aseprite, png::load_png, project::load_project, DocumentPath},
+    group::LayerGroup,
     image,
     layer::{Layer, LayerBundle},
-    HEIGHT, WIDTH,
+    onion::OnionSkin,
+    selection::Selection,
+    timeline::{GlobalTimeline, Timeline},
+    tools::MoveState,
+    History,
 };
 
+/// settings used when creating a new document.
+#[derive(Resource, Clone)]
+pub struct DocumentSettings {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for DocumentSettings {
+    fn default() -> Self {
+        DocumentSettings {
+            width: 512,
+            height: 512,
+        }
+    }
+}
+
+impl DocumentSettings {
+    /// parse a size like `32x32`
+    pub fn parse_size(size: &str) -> Option<(u32, u32)> {
+        let (width, height) = size.split_once(['x', 'X'])?;
+        let (width, height) = (width.trim().parse().ok()?, height.trim().parse().ok()?);
+        (width > 0 && height > 0).then_some((width, height))
+    }
+
+    pub fn empty_image(&self) -> Image {
+        image(self.width, self.height, Color::rgba(0.0, 0.0, 0.0, 0.0))
+    }
+}
+
 #[derive(Resource)]
 pub struct Canvas {
     pub width: u32,
@@ -80,31 +116,44 @@ pub fn cursor_position(
     }
 }
 
-pub fn setup_canvas(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
-    // create image
-    let image = images.add(image(WIDTH, HEIGHT, Color::rgba(0.0, 0.0, 0.0, 0.0)));
-
-    // spawn layer
-    let layer_id = commands
-        .spawn(LayerBundle {
-            layer: Layer::new(image.clone(), None),
-            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
-            texture: image.clone(),
-            ..Default::default()
-        })
-        .id();
+pub fn setup_canvas(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    path: Res<DocumentPath>,
+    settings: Res<DocumentSettings>,
+) {
+    // open the document if it exists, otherwise start with an empty canvas
+    let canvas = match path.0.extension().and_then(|ext| ext.to_str()) {
+        Some("sprinkle") if path.0.exists() => {
+            load_project(&path.0).map(|project| project.spawn(&mut commands, &mut images))
+        }
+        Some("ase" | "aseprite") if path.0.exists() => {
+            load_aseprite(&path.0).map(|project| project.spawn(&mut commands, &mut images))
+        }
+        Some("png") if path.0.exists() => {
+            load_png(&path.0).map(|image| spawn_image_layer(&mut commands, &mut images, image))
+        }
+        _ => Ok(spawn_image_layer(
+            &mut commands,
+            &mut images,
+            settings.empty_image(),
+        )),
+    }
+    .unwrap_or_else(|err| {
+        error!("failed to open {:?}: {}", path.0, err);
+        spawn_image_layer(&mut commands, &mut images, settings.empty_image())
+    });
 
     // insert canvas
-    commands.insert_resource(Canvas::new(WIDTH, HEIGHT, layer_id));
+    commands.insert_resource(canvas);
 
-    // shadow
+    // shadow, the texture is set by `shadow_texture`
     commands.spawn((
         SpriteBundle {
             sprite: Sprite {
                 color: Color::rgba(0.0, 0.0, 0.0, 0.2),
                 ..Default::default()
             },
-            texture: image,
             transform: Transform {
                 translation: Vec3::new(0.0, 0.0, 0.5),
                 ..Default::default()
@@ -115,6 +164,68 @@ pub fn setup_canvas(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
     ));
 }
 
+/// spawn a timeline with a single layer using `image` as its first frame, the canvas takes the size of the image.
+pub fn spawn_image_layer(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    image: Image,
+) -> Canvas {
+    let size = image.size().as_uvec2();
+    let image = images.add(image);
+    let timeline_id = commands.spawn_empty().id();
+
+    // spawn layer
+    let layer_id = commands
+        .spawn(LayerBundle {
+            layer: Layer::new("Layer 1", image.clone(), Some(timeline_id)),
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
+            texture: image,
+            ..Default::default()
+        })
+        .id();
+
+    commands
+        .entity(timeline_id)
+        .insert(Timeline::new(vec![layer_id], 0));
+
+    Canvas::new(size.x, size.y, layer_id)
+}
+
+/// [ctrl + n] : replace the current document with an empty one sized from [`DocumentSettings`]
+pub fn new_document(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    input: Res<Input<KeyCode>>,
+    settings: Res<DocumentSettings>,
+    document: Query<
+        Entity,
+        (
+            Or<(With<Layer>, With<LayerGroup>, With<Timeline>)>,
+            Without<Parent>,
+        ),
+    >,
+) {
+    if !(input.pressed(KeyCode::LControl) && input.just_pressed(KeyCode::N)) {
+        return;
+    }
+
+    for id in &document {
+        commands.entity(id).despawn_recursive();
+    }
+
+    let canvas = spawn_image_layer(&mut commands, &mut images, settings.empty_image());
+    commands.insert_resource(canvas);
+    commands.insert_resource(History::default());
+    commands.insert_resource(Selection::default());
+    commands.insert_resource(DocumentPath::default());
+    commands.insert_resource(GlobalTimeline::default());
+    // the moved pixels belong to the old document
+    commands.insert_resource(MoveState::default());
+    commands.insert_resource(OnionSkin::default());
+
+    info!("new document {}x{}", settings.width, settings.height);
+}
+
 #[derive(Component)]
 pub struct Shadow;
 
@@ -132,6 +243,20 @@ pub fn shadow_paralax(
     bg.translation = (cam_pos.xy() * -0.04).extend(0.0);
 }
 
+/// make the shadow follow the composite of every layer.
+pub fn shadow_texture(
+    composite: Query<&Handle<Image>, (With<Composite>, Without<Shadow>)>,
+    mut shadow: Query<&mut Handle<Image>, (With<Shadow>, Without<Composite>)>,
+) {
+    let (Ok(texture), Ok(mut shadow)) = (composite.get_single(), shadow.get_single_mut()) else {
+        return;
+    };
+
+    if *shadow != *texture {
+        *shadow = texture.clone();
+    }
+}
+
 // #[derive(Component)]
 // pub struct CursorPreview;
 
@@ -0,0 +1,342 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{canvas::Canvas, group::LayerGroup, layer::Layer, timeline::Timeline};
+
+/// how a layer is mixed with the layers below it.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Add,
+    Darken,
+    Lighten,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 7] = [
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Overlay,
+        BlendMode::Add,
+        BlendMode::Darken,
+        BlendMode::Lighten,
+    ];
+
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|mode| *mode == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// blend a single color channel of the `src` layer with the `dst` backdrop, both in `0.0..=1.0`.
+    fn channel(self, dst: f32, src: f32) -> f32 {
+        match self {
+            BlendMode::Normal => src,
+            BlendMode::Multiply => dst * src,
+            BlendMode::Screen => dst + src - dst * src,
+            BlendMode::Overlay => {
+                if dst <= 0.5 {
+                    2.0 * dst * src
+                } else {
+                    1.0 - 2.0 * (1.0 - dst) * (1.0 - src)
+                }
+            }
+            BlendMode::Add => (dst + src).min(1.0),
+            BlendMode::Darken => dst.min(src),
+            BlendMode::Lighten => dst.max(src),
+        }
+    }
+}
+
+/// composite `src` on top of `dst` (straight alpha, source over).
+///
+/// both slices are rgba8 and must have the same length.
+pub fn alpha_over(dst: &mut [u8], src: &[u8]) {
+    blend(dst, src, 1.0, BlendMode::Normal);
+}
+
+pub fn alpha_over_pixel(dst: [u8; 4], src: [u8; 4]) -> [u8; 4] {
+    blend_pixel(dst, src, 1.0, BlendMode::Normal)
+}
+
+/// composite `src` on top of `dst` with the given opacity and blend mode.
+pub fn blend(dst: &mut [u8], src: &[u8], opacity: f32, mode: BlendMode) {
+    for (d, s) in dst.array_chunks_mut::<4>().zip(src.array_chunks::<4>()) {
+        *d = blend_pixel(*d, *s, opacity, mode);
+    }
+}
+
+/// separable blending followed by source over, as described in the w3c compositing spec.
+pub fn blend_pixel(dst: [u8; 4], src: [u8; 4], opacity: f32, mode: BlendMode) -> [u8; 4] {
+    let sa = src[3] as f32 / 255.0 * opacity.clamp(0.0, 1.0);
+    if sa == 0.0 {
+        return dst;
+    }
+    if mode == BlendMode::Normal && (sa == 1.0 || dst[3] == 0) {
+        return [src[0], src[1], src[2], (sa * 255.0).round() as u8];
+    }
+
+    let da = dst[3] as f32 / 255.0;
+    let a = sa + da * (1.0 - sa);
+
+    let channel = |s: u8, d: u8| -> u8 {
+        let (s, d) = (s as f32 / 255.0, d as f32 / 255.0);
+        // where the backdrop is transparent the source color is used as is
+        let s = (1.0 - da) * s + da * mode.channel(d, s);
+        let c = (sa * s + (1.0 - sa) * da * d) / a;
+        (c * 255.0).round().clamp(0.0, 255.0) as u8
+    };
+
+    [
+        channel(src[0], dst[0]),
+        channel(src[1], dst[1]),
+        channel(src[2], dst[2]),
+        (a * 255.0).round() as u8,
+    ]
+}
+
+/// flatten `layers` (bottom first) into a single transparent image.
+pub fn flatten<'a>(
+    width: u32,
+    height: u32,
+    layers: impl IntoIterator<Item = (&'a [u8], f32, BlendMode)>,
+) -> Vec<u8> {
+    let mut data = vec![0; (width * height * 4) as usize];
+    for (layer, opacity, mode) in layers {
+        blend(&mut data, layer, opacity, mode);
+    }
+    data
+}
+
+/// the layers and groups of every timeline, used to composite the document.
+#[derive(SystemParam)]
+pub struct LayerTree<'w, 's> {
+    pub layers: Query<'w, 's, (&'static Layer, &'static Handle<Image>)>,
+    pub groups: Query<'w, 's, (&'static LayerGroup, Option<&'static Children>)>,
+    pub parents: Query<'w, 's, &'static Parent>,
+    pub timelines: Query<'w, 's, &'static Timeline>,
+}
+
+impl LayerTree<'_, '_> {
+    /// flatten every visible layer of every timeline.
+    ///
+    /// each layer is drawn using its image at `frame` of the
+    /// [`GlobalTimeline`](crate::timeline::GlobalTimeline), or its current texture if `None`.
+    pub fn flatten(
+        &self,
+        width: u32,
+        height: u32,
+        frame: Option<i32>,
+        images: &Assets<Image>,
+    ) -> Vec<u8> {
+        let mut data = vec![0; (width * height * 4) as usize];
+        for timeline in &self.timelines {
+            let frame = frame.map(|frame| timeline.local_frame(frame));
+            self.flatten_nodes(&mut data, timeline.layers(), frame, images);
+        }
+        data
+    }
+
+    /// draw `nodes` (bottom first) on top of `data`, groups are flattened on their own
+    /// before being blended with the nodes below them.
+    ///
+    /// `frame` is a frame of the timeline the nodes are in.
+    pub fn flatten_nodes(
+        &self,
+        data: &mut [u8],
+        nodes: &[Entity],
+        frame: Option<i32>,
+        images: &Assets<Image>,
+    ) {
+        for node in nodes {
+            if let Ok((layer, texture)) = self.layers.get(*node) {
+                if !layer.visible {
+                    continue;
+                }
+                let handle = match frame {
+                    Some(frame) => layer.frames.get(&frame),
+                    None => Some(texture),
+                };
+                if let Some(image) = handle.and_then(|handle| images.get(handle)) {
+                    blend(data, &image.data, layer.opacity, layer.blend_mode);
+                }
+            } else if let Ok((group, Some(children))) = self.groups.get(*node) {
+                if !group.visible {
+                    continue;
+                }
+                let mut group_data = vec![0; data.len()];
+                self.flatten_nodes(&mut group_data, children, frame, images);
+                blend(data, &group_data, group.opacity, group.blend_mode);
+            }
+        }
+    }
+
+    /// a layer is hidden if it or one of its groups is hidden.
+    pub fn is_visible(&self, layer_id: Entity) -> bool {
+        let Ok((layer, _)) = self.layers.get(layer_id) else {
+            return false;
+        };
+        if !layer.visible {
+            return false;
+        }
+
+        let mut id = layer_id;
+        while let Ok(parent) = self.parents.get(id) {
+            id = parent.get();
+            if let Ok((group, _)) = self.groups.get(id) {
+                if !group.visible {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// tools refuse to draw on hidden or locked layers.
+    pub fn is_editable(&self, layer_id: Entity) -> bool {
+        self.is_visible(layer_id)
+            && self
+                .layers
+                .get(layer_id)
+                .map_or(false, |(layer, _)| !layer.locked)
+    }
+}
+
+/// the sprite showing every layer composited together.
+#[derive(Component)]
+pub struct Composite;
+
+pub fn setup_composite(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let image = images.add(crate::image(1, 1, Color::NONE));
+
+    commands.spawn((
+        SpriteBundle {
+            texture: image,
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
+            ..Default::default()
+        },
+        Composite,
+    ));
+}
+
+/// redraw the composite when a layer, group or one of the images changed.
+pub fn update_composite(
+    canvas: Res<Canvas>,
+    tree: LayerTree,
+    changed: Query<
+        (),
+        Or<(
+            Changed<Layer>,
+            (With<Layer>, Changed<Handle<Image>>),
+            Changed<LayerGroup>,
+            (With<LayerGroup>, Changed<Children>),
+            Changed<Timeline>,
+        )>,
+    >,
+    mut removed_layers: RemovedComponents<Layer>,
+    mut removed_groups: RemovedComponents<LayerGroup>,
+    mut events: EventReader<AssetEvent<Image>>,
+    composite: Query<&Handle<Image>, With<Composite>>,
+    mut images: ResMut<Assets<Image>>,
+    mut size: Local<UVec2>,
+) {
+    let composite = composite.single();
+
+    let canvas_size = UVec2::new(canvas.width, canvas.height);
+    let mut dirty = *size != canvas_size
+        || !changed.is_empty()
+        || removed_layers.iter().count() > 0
+        || removed_groups.iter().count() > 0;
+    *size = canvas_size;
+    for ev in events.iter() {
+        if let AssetEvent::Modified { handle } | AssetEvent::Created { handle } = ev {
+            dirty |= handle != composite
+                && tree.layers.iter().any(|(layer, texture)| {
+                    texture == handle || layer.frames.values().any(|h| h == handle)
+                });
+        }
+    }
+    if !dirty {
+        return;
+    }
+
+    let data = tree.flatten(canvas.width, canvas.height, None, &images);
+
+    let image = images.get_mut(composite).unwrap();
+    image.data = data;
+    crate::resize::set_image_size(image, canvas.width, canvas.height);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0.2, 0.4 and 0.8 under 0.6 gray, both opaque
+    const DST: [u8; 4] = [51, 102, 204, 255];
+    const SRC: [u8; 4] = [153, 153, 153, 255];
+
+    #[test]
+    fn blend_modes() {
+        for (mode, expected) in [
+            (BlendMode::Normal, [153, 153, 153, 255]),
+            (BlendMode::Multiply, [31, 61, 122, 255]),
+            (BlendMode::Screen, [173, 194, 235, 255]),
+            (BlendMode::Overlay, [61, 122, 214, 255]),
+            (BlendMode::Add, [204, 255, 255, 255]),
+            (BlendMode::Darken, [51, 102, 153, 255]),
+            (BlendMode::Lighten, [153, 153, 204, 255]),
+        ] {
+            assert_eq!(blend_pixel(DST, SRC, 1.0, mode), expected, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn partial_alpha() {
+        let half_red = [255, 0, 0, 128];
+        assert_eq!(
+            blend_pixel([0, 0, 255, 255], half_red, 1.0, BlendMode::Normal),
+            [128, 0, 127, 255]
+        );
+        assert_eq!(
+            blend_pixel([0, 0, 255, 128], half_red, 1.0, BlendMode::Normal),
+            [170, 0, 85, 192]
+        );
+        // nothing is drawn where the source is transparent
+        assert_eq!(
+            blend_pixel(DST, [255, 255, 255, 0], 1.0, BlendMode::Multiply),
+            DST
+        );
+    }
+
+    #[test]
+    fn opacity() {
+        let mut data = [0, 0, 0, 255, 0, 0, 0, 0];
+        blend(&mut data, &[255; 8], 0.25, BlendMode::Normal);
+        assert_eq!(data, [64, 64, 64, 255, 255, 255, 255, 64]);
+    }
+
+    #[test]
+    fn group_opacity_applies_to_the_flattened_group() {
+        let red = [255, 0, 0, 255];
+        let green = [0, 255, 0, 255];
+        let mut data = vec![0, 0, 0, 255];
+
+        // the way `LayerTree::flatten_nodes` draws a group
+        let group = flatten(
+            1,
+            1,
+            [
+                (&red[..], 1.0, BlendMode::Normal),
+                (&green[..], 1.0, BlendMode::Normal),
+            ],
+        );
+        blend(&mut data, &group, 0.25, BlendMode::Normal);
+
+        // the red layer is covered inside the group, it doesn't shine through
+        assert_eq!(data, [0, 64, 0, 255]);
+    }
+}
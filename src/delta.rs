@@ -0,0 +1,111 @@
+use std::io::{Read, Write};
+
+use bevy::prelude::*;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+/// the change to a rectangle of an rgba8 image, stored by the [`History`](crate::History).
+///
+/// the pixels before and after are xor'd together, so applying the delta turns either state
+/// into the other. the pixels that didn't change are zeros, which compress to almost nothing.
+#[derive(Clone)]
+pub struct Delta {
+    /// the top left corner of the changed rectangle
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// zlib compressed
+    data: Vec<u8>,
+}
+
+impl Delta {
+    /// the difference between two images `width` pixels wide, `None` if they're the same.
+    pub fn new(before: &[u8], after: &[u8], width: u32) -> Option<Self> {
+        let (mut min, mut max) = (UVec2::MAX, UVec2::ZERO);
+        let pixels = before.array_chunks::<4>().zip(after.array_chunks::<4>());
+        for (i, (a, b)) in pixels.enumerate() {
+            if a != b {
+                let pos = UVec2::new(i as u32 % width, i as u32 / width);
+                min = min.min(pos);
+                max = max.max(pos);
+            }
+        }
+        if min.x > max.x {
+            return None;
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        for y in min.y..=max.y {
+            let row = ((y * width + min.x) * 4) as usize..((y * width + max.x + 1) * 4) as usize;
+            let xor = before[row.clone()]
+                .iter()
+                .zip(&after[row])
+                .map(|(a, b)| a ^ b)
+                .collect::<Vec<_>>();
+            encoder.write_all(&xor).unwrap();
+        }
+
+        Some(Delta {
+            x: min.x,
+            y: min.y,
+            width: max.x - min.x + 1,
+            height: max.y - min.y + 1,
+            data: encoder.finish().unwrap(),
+        })
+    }
+
+    /// turn one state of an image `width` pixels wide into the other.
+    pub fn apply(&self, image: &mut [u8], width: u32) {
+        if self.x + self.width > width
+            || ((self.y + self.height) * width * 4) as usize > image.len()
+        {
+            warn!("the image is smaller than the change, it's left as is");
+            return;
+        }
+
+        let mut xor = Vec::new();
+        ZlibDecoder::new(&self.data[..])
+            .read_to_end(&mut xor)
+            .unwrap();
+        for (row, y) in xor.chunks((self.width * 4) as usize).zip(self.y..) {
+            let start = ((y * width + self.x) * 4) as usize;
+            for (pixel, x) in image[start..start + row.len()].iter_mut().zip(row) {
+                *pixel ^= x;
+            }
+        }
+    }
+
+    /// how many bytes the compressed pixels take up.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let width = 4;
+        let before = (0..4 * 3 * 4).map(|i| i as u8).collect::<Vec<_>>();
+        let mut after = before.clone();
+        after[4 * 5..4 * 6].copy_from_slice(&[255, 0, 0, 128]);
+        after[4 * 10..4 * 11].copy_from_slice(&[0, 0, 0, 0]);
+
+        let delta = Delta::new(&before, &after, width).unwrap();
+        assert_eq!((delta.x, delta.y, delta.width, delta.height), (1, 1, 2, 2));
+
+        let mut image = before.clone();
+        delta.apply(&mut image, width);
+        assert_eq!(image, after);
+        delta.apply(&mut image, width);
+        assert_eq!(image, before);
+    }
+
+    #[test]
+    fn unchanged_image() {
+        let image = vec![7; 4 * 4 * 4];
+        assert!(Delta::new(&image, &image, 4).is_none());
+    }
+}
@@ -0,0 +1,1368 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+use bevy::{prelude::*, utils::HashMap};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{
+    canvas::Canvas,
+    composite::BlendMode,
+    group::{Detached, LayerGroup},
+    layer::Layer,
+    timeline::{Tag, TagDirection, Timeline, DEFAULT_DURATION},
+    ColorPalette, ColorState,
+};
+
+use super::{
+    project::{GroupData, LayerData, NodeData, Project, TimelineData},
+    sheet::{visible_rect, SheetRect},
+    DocumentPath, FileError,
+};
+
+// see https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+pub const FILE_MAGIC: u16 = 0xA5E0;
+pub const FRAME_MAGIC: u16 = 0xF1FA;
+
+pub const CHUNK_OLD_PALETTE: u16 = 0x0004;
+pub const CHUNK_OLD_PALETTE_64: u16 = 0x0011;
+pub const CHUNK_LAYER: u16 = 0x2004;
+pub const CHUNK_CEL: u16 = 0x2005;
+pub const CHUNK_COLOR_PROFILE: u16 = 0x2007;
+pub const CHUNK_TAGS: u16 = 0x2018;
+pub const CHUNK_PALETTE: u16 = 0x2019;
+
+pub const LAYER_VISIBLE: u16 = 1;
+pub const LAYER_EDITABLE: u16 = 2;
+pub const LAYER_BACKGROUND: u16 = 8;
+pub const LAYER_COLLAPSED: u16 = 32;
+
+pub const LAYER_NORMAL: u16 = 0;
+pub const LAYER_GROUP: u16 = 1;
+
+pub const CEL_RAW: u16 = 0;
+pub const CEL_LINKED: u16 = 1;
+pub const CEL_COMPRESSED: u16 = 2;
+
+/// the header flag saying layer opacity is stored
+pub const FLAG_LAYER_OPACITY: u32 = 1;
+
+/// aseprite blend modes in file order, the ones sprinkle doesn't have are drawn as normal.
+pub fn blend_mode_from_ase(mode: u16) -> Option<BlendMode> {
+    match mode {
+        0 => Some(BlendMode::Normal),
+        1 => Some(BlendMode::Multiply),
+        2 => Some(BlendMode::Screen),
+        3 => Some(BlendMode::Overlay),
+        4 => Some(BlendMode::Darken),
+        5 => Some(BlendMode::Lighten),
+        16 => Some(BlendMode::Add),
+        _ => None,
+    }
+}
+
+pub fn blend_mode_to_ase(mode: BlendMode) -> u16 {
+    match mode {
+        BlendMode::Normal => 0,
+        BlendMode::Multiply => 1,
+        BlendMode::Screen => 2,
+        BlendMode::Overlay => 3,
+        BlendMode::Darken => 4,
+        BlendMode::Lighten => 5,
+        BlendMode::Add => 16,
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> FileError {
+    FileError::InvalidAseprite(msg.into())
+}
+
+/// little endian reader over the bytes of a file or chunk.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], FileError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), FileError> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn byte(&mut self) -> Result<u8, FileError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, FileError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn short(&mut self) -> Result<i16, FileError> {
+        Ok(i16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn dword(&mut self) -> Result<u32, FileError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, FileError> {
+        let len = self.word()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+}
+
+struct AseLayer {
+    flags: u16,
+    kind: u16,
+    child_level: u16,
+    blend_mode: u16,
+    opacity: u8,
+    name: String,
+}
+
+enum AseCel {
+    Image {
+        x: i16,
+        y: i16,
+        width: u32,
+        height: u32,
+        opacity: u8,
+        /// in the color depth of the file
+        pixels: Vec<u8>,
+    },
+    /// the frame this cel shares its image with
+    Linked(u16),
+}
+
+/// `limit` is the size of the whole sprite in bytes, no cel can be bigger than that.
+fn read_cel(
+    chunk: &mut Reader,
+    depth: usize,
+    limit: usize,
+) -> Result<Option<(usize, AseCel)>, FileError> {
+    let layer = chunk.word()? as usize;
+    let x = chunk.short()?;
+    let y = chunk.short()?;
+    let opacity = chunk.byte()?;
+    let kind = chunk.word()?;
+    let _z_index = chunk.short()?;
+    chunk.skip(5)?;
+
+    let cel = match kind {
+        CEL_RAW | CEL_COMPRESSED => {
+            let width = chunk.word()? as u32;
+            let height = chunk.word()? as u32;
+            let len = width as usize * height as usize * depth;
+            if len > limit {
+                return Err(invalid(format!(
+                    "cel of {width}x{height} is bigger than the sprite"
+                )));
+            }
+            let pixels = if kind == CEL_RAW {
+                chunk.bytes(len)?.to_vec()
+            } else {
+                // whatever inflates past the size of the cel is ignored
+                let mut pixels = Vec::new();
+                ZlibDecoder::new(&chunk.data[chunk.pos..])
+                    .take(len as u64)
+                    .read_to_end(&mut pixels)?;
+                pixels
+            };
+            if pixels.len() < len {
+                return Err(invalid("cel has less pixels than its size"));
+            }
+            AseCel::Image {
+                x,
+                y,
+                width,
+                height,
+                opacity,
+                pixels,
+            }
+        }
+        CEL_LINKED => AseCel::Linked(chunk.word()?),
+        _ => {
+            warn!("skipped tilemap cel on layer {layer}, tilemaps aren't supported");
+            return Ok(None);
+        }
+    };
+    Ok(Some((layer, cel)))
+}
+
+/// read the entries of a palette chunk into `palette`.
+fn read_palette(chunk: &mut Reader, palette: &mut Vec<[u8; 4]>) -> Result<(), FileError> {
+    let size = chunk.dword()? as usize;
+    let first = chunk.dword()? as usize;
+    let last = chunk.dword()? as usize;
+    chunk.skip(8)?;
+
+    if palette.len() < size {
+        palette.resize(size, [0, 0, 0, 255]);
+    }
+    for i in first..=last {
+        let flags = chunk.word()?;
+        let color = chunk.bytes(4)?.try_into().unwrap();
+        if flags & 1 != 0 {
+            chunk.string()?;
+        }
+        if i >= palette.len() {
+            palette.resize(i + 1, [0, 0, 0, 255]);
+        }
+        palette[i] = color;
+    }
+    Ok(())
+}
+
+/// read the palette chunks of old versions, `six_bit` colors go from 0 to 63.
+fn read_old_palette(
+    chunk: &mut Reader,
+    palette: &mut Vec<[u8; 4]>,
+    six_bit: bool,
+) -> Result<(), FileError> {
+    let packets = chunk.word()?;
+    let mut i = 0;
+    for _ in 0..packets {
+        i += chunk.byte()? as usize;
+        let count = match chunk.byte()? {
+            0 => 256,
+            count => count as usize,
+        };
+        for _ in 0..count {
+            let mut color = [0, 0, 0, 255];
+            for c in &mut color[..3] {
+                let value = chunk.byte()?;
+                *c = if six_bit {
+                    (value << 2) | (value >> 4)
+                } else {
+                    value
+                };
+            }
+            if i >= palette.len() {
+                palette.resize(i + 1, [0, 0, 0, 255]);
+            }
+            palette[i] = color;
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
+fn read_tags(chunk: &mut Reader) -> Result<Vec<Tag>, FileError> {
+    let count = chunk.word()?;
+    chunk.skip(8)?;
+
+    let mut tags = Vec::new();
+    for _ in 0..count {
+        let from = chunk.word()? as i32;
+        let to = chunk.word()? as i32;
+        let direction = match chunk.byte()? {
+            0 => TagDirection::Forward,
+            1 => TagDirection::Reverse,
+            2 => TagDirection::PingPong,
+            direction => {
+                warn!("tag direction {direction} isn't supported, using ping-pong");
+                TagDirection::PingPong
+            }
+        };
+        let _repeat = chunk.word()?;
+        chunk.skip(6 + 3 + 1)?;
+        let name = chunk.string()?;
+        tags.push(Tag {
+            name,
+            from,
+            to,
+            direction,
+        });
+    }
+    Ok(tags)
+}
+
+/// draw a cel on a transparent canvas sized image.
+fn cel_to_rgba(
+    cel: &AseCel,
+    width: u32,
+    height: u32,
+    depth: usize,
+    palette: &[[u8; 4]],
+    transparent_index: Option<u8>,
+) -> Vec<u8> {
+    let mut data = vec![0; width as usize * height as usize * 4];
+    let AseCel::Image {
+        x,
+        y,
+        width: cel_width,
+        height: cel_height,
+        opacity,
+        pixels,
+    } = cel
+    else {
+        return data;
+    };
+
+    for cy in 0..*cel_height {
+        for cx in 0..*cel_width {
+            let (px, py) = (*x as i32 + cx as i32, *y as i32 + cy as i32);
+            if px < 0 || py < 0 || px >= width as i32 || py >= height as i32 {
+                continue;
+            }
+            let src = ((cy * cel_width + cx) as usize) * depth;
+            let mut color = match depth {
+                4 => pixels[src..src + 4].try_into().unwrap(),
+                2 => [pixels[src], pixels[src], pixels[src], pixels[src + 1]],
+                _ if Some(pixels[src]) == transparent_index => [0, 0, 0, 0],
+                _ => palette
+                    .get(pixels[src] as usize)
+                    .copied()
+                    .unwrap_or([0, 0, 0, 0]),
+            };
+            color[3] = (color[3] as u32 * *opacity as u32 / 255) as u8;
+
+            let dst = ((py as u32 * width + px as u32) * 4) as usize;
+            data[dst..dst + 4].copy_from_slice(&color);
+        }
+    }
+    data
+}
+
+/// read an aseprite file into a [`Project`] with a single timeline.
+///
+/// chunks sprinkle has no use for, like slices or tilemaps, are skipped with a warning.
+pub fn parse_aseprite(data: &[u8]) -> Result<Project, FileError> {
+    let mut reader = Reader::new(data);
+
+    // header
+    let _file_size = reader.dword()?;
+    if reader.word()? != FILE_MAGIC {
+        return Err(invalid("wrong magic number"));
+    }
+    let frame_count = reader.word()? as usize;
+    let width = reader.word()? as u32;
+    let height = reader.word()? as u32;
+    let depth = match reader.word()? {
+        32 => 4,
+        16 => 2,
+        8 => 1,
+        depth => return Err(invalid(format!("unknown color depth {depth}"))),
+    };
+    let flags = reader.dword()?;
+    reader.skip(2 + 4 + 4)?;
+    let transparent_index = reader.byte()?;
+    reader.skip(3 + 2 + 1 + 1 + 2 + 2 + 2 + 2 + 84)?;
+    if width == 0 || height == 0 {
+        return Err(invalid("the sprite has no size"));
+    }
+
+    let mut layers = Vec::<AseLayer>::new();
+    let mut cels = HashMap::<(usize, usize), AseCel>::new();
+    let mut durations = Vec::new();
+    let mut tags = Vec::new();
+    let mut palette = Vec::new();
+    let mut old_palette = Vec::new();
+    let mut skipped = BTreeSet::new();
+
+    for frame in 0..frame_count {
+        let start = reader.pos;
+        let frame_size = reader.dword()? as usize;
+        if reader.word()? != FRAME_MAGIC {
+            return Err(invalid(format!("frame {frame} has the wrong magic number")));
+        }
+        let old_chunk_count = reader.word()? as u32;
+        durations.push(reader.word()? as u32);
+        reader.skip(2)?;
+        let chunk_count = match reader.dword()? {
+            0 => old_chunk_count,
+            count => count,
+        };
+
+        for _ in 0..chunk_count {
+            let size = reader.dword()? as usize;
+            let kind = reader.word()?;
+            let mut chunk = Reader::new(reader.bytes(size.saturating_sub(6))?);
+
+            match kind {
+                CHUNK_LAYER => {
+                    let flags = chunk.word()?;
+                    let layer_kind = chunk.word()?;
+                    let child_level = chunk.word()?;
+                    chunk.skip(4)?;
+                    let blend_mode = chunk.word()?;
+                    let opacity = chunk.byte()?;
+                    chunk.skip(3)?;
+                    let name = chunk.string()?;
+                    if layer_kind != LAYER_NORMAL && layer_kind != LAYER_GROUP {
+                        warn!("{name} is a tilemap, it's imported as an empty layer");
+                    }
+                    layers.push(AseLayer {
+                        flags,
+                        kind: layer_kind,
+                        child_level,
+                        blend_mode,
+                        opacity,
+                        name,
+                    });
+                }
+                CHUNK_CEL => {
+                    let limit = width as usize * height as usize * depth;
+                    if let Some((layer, cel)) = read_cel(&mut chunk, depth, limit)? {
+                        cels.insert((layer, frame), cel);
+                    }
+                }
+                CHUNK_TAGS => tags = read_tags(&mut chunk)?,
+                CHUNK_PALETTE => read_palette(&mut chunk, &mut palette)?,
+                CHUNK_OLD_PALETTE => read_old_palette(&mut chunk, &mut old_palette, false)?,
+                CHUNK_OLD_PALETTE_64 => read_old_palette(&mut chunk, &mut old_palette, true)?,
+                // colors are used as they are
+                CHUNK_COLOR_PROFILE => (),
+                _ => {
+                    skipped.insert(kind);
+                }
+            }
+        }
+
+        reader.pos = start + frame_size;
+    }
+
+    for kind in skipped {
+        warn!("skipped unsupported aseprite chunk {kind:#06x}");
+    }
+    if palette.is_empty() {
+        palette = old_palette;
+    }
+    // layer opacity is only stored if this flag is set
+    if flags & FLAG_LAYER_OPACITY == 0 {
+        for layer in &mut layers {
+            layer.opacity = 255;
+        }
+    }
+
+    // the layers are listed bottom first with the children of a group right after it
+    let mut layers_data = Vec::new();
+    let mut groups = Vec::<GroupData>::new();
+    let mut top_level = Vec::new();
+    let mut parents = Vec::<usize>::new();
+    let mut layer_indices = HashMap::new();
+    for (i, layer) in layers.iter().enumerate() {
+        parents.truncate(layer.child_level as usize);
+
+        let blend_mode = blend_mode_from_ase(layer.blend_mode).unwrap_or_else(|| {
+            warn!(
+                "{} uses blend mode {} which isn't supported, using normal",
+                layer.name, layer.blend_mode
+            );
+            BlendMode::Normal
+        });
+        let visible = layer.flags & LAYER_VISIBLE != 0;
+        let opacity = layer.opacity as f32 / 255.0;
+
+        let node = if layer.kind == LAYER_GROUP {
+            groups.push(GroupData {
+                name: layer.name.clone(),
+                visible,
+                opacity,
+                blend_mode,
+                collapsed: layer.flags & LAYER_COLLAPSED != 0,
+                children: Vec::new(),
+            });
+            NodeData::Group {
+                group: groups.len() - 1,
+            }
+        } else {
+            layer_indices.insert(i, layers_data.len());
+            layers_data.push(LayerData {
+                name: layer.name.clone(),
+                z: layers_data.len() as f32 + 1.0,
+                visible,
+                locked: layer.flags & LAYER_EDITABLE == 0,
+                opacity,
+                blend_mode,
+                onion_skin: true,
+                timeline: Some(0),
+                frames: Vec::new(),
+                links: Vec::new(),
+            });
+            NodeData::Layer(layers_data.len() - 1)
+        };
+
+        match parents.last() {
+            Some(parent) => groups[*parent].children.push(node),
+            None => top_level.push(node),
+        }
+        if let NodeData::Group { group } = node {
+            parents.push(group);
+        }
+    }
+
+    // the background layer is opaque, even where the color is the transparent index
+    let background = layers
+        .iter()
+        .position(|layer| layer.flags & LAYER_BACKGROUND != 0);
+
+    let mut sorted = cels.keys().copied().collect::<Vec<_>>();
+    sorted.sort();
+    for (layer, frame) in sorted {
+        let Some(index) = layer_indices.get(&layer) else {
+            continue;
+        };
+        let data = &mut layers_data[*index];
+        match &cels[&(layer, frame)] {
+            AseCel::Linked(other) => {
+                let other = *other as i32;
+                if data.frames.iter().any(|(f, _)| *f == other) {
+                    data.links.push((frame as i32, other));
+                } else {
+                    warn!(
+                        "{} frame {} links to the missing frame {}",
+                        data.name, frame, other
+                    );
+                }
+            }
+            cel => {
+                let transparent = (background != Some(layer)).then_some(transparent_index);
+                let pixels = cel_to_rgba(cel, width, height, depth, &palette, transparent);
+                data.frames.push((frame as i32, pixels));
+            }
+        }
+    }
+
+    // a document always has a layer, and empty frames at the end still count
+    if layers_data.is_empty() {
+        top_level.push(NodeData::Layer(0));
+        layers_data.push(LayerData {
+            name: String::from("Layer 1"),
+            z: 1.0,
+            visible: true,
+            locked: false,
+            opacity: 1.0,
+            blend_mode: BlendMode::Normal,
+            onion_skin: true,
+            timeline: Some(0),
+            frames: Vec::new(),
+            links: Vec::new(),
+        });
+    }
+    let last = frame_count.max(1) as i32 - 1;
+    let has_last = layers_data.iter().any(|layer| {
+        layer.frames.iter().any(|(frame, _)| *frame == last)
+            || layer.links.iter().any(|(frame, _)| *frame == last)
+    });
+    if !has_last {
+        layers_data[0]
+            .frames
+            .push((last, vec![0; width as usize * height as usize * 4]));
+    }
+
+    let colors = palette
+        .iter()
+        .map(|[r, g, b, a]| Color::rgba_u8(*r, *g, *b, *a))
+        .collect::<Vec<_>>();
+    let color_state = if depth == 1 && !colors.is_empty() {
+        // start with the first color that isn't transparent
+        let primary = (transparent_index == 0 && colors.len() > 1) as u8;
+        ColorState::Indexed {
+            primary,
+            secondary: if (transparent_index as usize) < colors.len() {
+                transparent_index
+            } else {
+                0
+            },
+        }
+    } else {
+        ColorState::default()
+    };
+    let colors = if colors.is_empty() {
+        vec![Color::WHITE, Color::BLACK]
+    } else {
+        colors
+    };
+
+    let active_layer = layers_data.len() - 1;
+    let project = Project {
+        width,
+        height,
+        active_layer,
+        layers: layers_data,
+        groups,
+        timelines: vec![TimelineData {
+            layers: top_level,
+            frame_offset: 0,
+            durations,
+            tags,
+        }],
+        palette: ColorPalette {
+            palette: colors,
+            color_state,
+        },
+    };
+    if !project.is_valid() {
+        return Err(invalid("the layers or cels don't fit together"));
+    }
+    Ok(project)
+}
+
+pub fn load_aseprite(path: impl AsRef<Path>) -> Result<Project, FileError> {
+    parse_aseprite(&fs::read(path)?)
+}
+
+/// little endian writer for the chunks of an aseprite file.
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn zeros(&mut self, len: usize) {
+        self.0.resize(self.0.len() + len, 0);
+    }
+
+    fn byte(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn word(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn short(&mut self, value: i16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn dword(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        let len = value.len().min(u16::MAX as usize);
+        self.word(len as u16);
+        self.bytes(&value.as_bytes()[..len]);
+    }
+
+    /// write a chunk with its size and type in front of it.
+    fn chunk(&mut self, kind: u16, data: Writer) {
+        self.dword(data.0.len() as u32 + 6);
+        self.word(kind);
+        self.bytes(&data.0);
+    }
+}
+
+/// maps colors to palette indices for indexed files.
+struct IndexedColors {
+    indices: HashMap<[u8; 4], u8>,
+    transparent: u8,
+}
+
+impl IndexedColors {
+    /// `None` if a color of the document isn't in the palette, or there's no room for a
+    /// transparent color. the transparent color is added to `palette` if it isn't in it.
+    fn new(project: &Project, palette: &mut Vec<[u8; 4]>) -> Option<Self> {
+        let transparent = match palette.iter().position(|color| color[3] == 0) {
+            Some(i) => i,
+            None => {
+                palette.push([0, 0, 0, 0]);
+                palette.len() - 1
+            }
+        };
+        if palette.len() > 256 {
+            return None;
+        }
+
+        let mut indices = HashMap::new();
+        for (i, color) in palette.iter().enumerate().rev() {
+            indices.insert(*color, i as u8);
+        }
+        let fits = project
+            .layers
+            .iter()
+            .flat_map(|layer| &layer.frames)
+            .flat_map(|(_, data)| data.array_chunks::<4>())
+            .all(|pixel| pixel[3] == 0 || indices.contains_key(pixel));
+        fits.then_some(IndexedColors {
+            indices,
+            transparent: transparent as u8,
+        })
+    }
+
+    fn index(&self, pixel: &[u8; 4]) -> u8 {
+        match pixel[3] {
+            0 => self.transparent,
+            _ => self.indices[pixel],
+        }
+    }
+}
+
+/// a layer or group in the order aseprite stores them, with its child level and the frame
+/// offset of its timeline.
+fn ordered_nodes(
+    project: &Project,
+    nodes: &[NodeData],
+    level: u16,
+    offset: i32,
+    out: &mut Vec<(NodeData, u16, i32)>,
+) {
+    for node in nodes {
+        out.push((*node, level, offset));
+        if let NodeData::Group { group } = node {
+            ordered_nodes(
+                project,
+                &project.groups[*group].children,
+                level + 1,
+                offset,
+                out,
+            );
+        }
+    }
+}
+
+fn layer_chunk(project: &Project, node: NodeData, level: u16) -> Writer {
+    let (flags, kind, name, opacity, blend_mode) = match node {
+        NodeData::Layer(i) => {
+            let layer = &project.layers[i];
+            let flags =
+                (layer.visible as u16 * LAYER_VISIBLE) | (!layer.locked as u16 * LAYER_EDITABLE);
+            (
+                flags,
+                LAYER_NORMAL,
+                &layer.name,
+                layer.opacity,
+                layer.blend_mode,
+            )
+        }
+        NodeData::Group { group } => {
+            let group = &project.groups[group];
+            let flags = (group.visible as u16 * LAYER_VISIBLE)
+                | LAYER_EDITABLE
+                | (group.collapsed as u16 * LAYER_COLLAPSED);
+            (
+                flags,
+                LAYER_GROUP,
+                &group.name,
+                group.opacity,
+                group.blend_mode,
+            )
+        }
+    };
+
+    let mut chunk = Writer::default();
+    chunk.word(flags);
+    chunk.word(kind);
+    chunk.word(level);
+    chunk.zeros(4);
+    chunk.word(blend_mode_to_ase(blend_mode));
+    chunk.byte((opacity.clamp(0.0, 1.0) * 255.0).round() as u8);
+    chunk.zeros(3);
+    chunk.string(name);
+    chunk
+}
+
+/// a compressed cel of the visible part of `data`, `None` if it's fully transparent.
+fn image_cel_chunk(
+    project: &Project,
+    layer: u16,
+    data: &[u8],
+    colors: Option<&IndexedColors>,
+    keep_empty: bool,
+) -> Result<Option<Writer>, FileError> {
+    let rect = match visible_rect(data, project.width, project.height) {
+        Some(rect) => rect,
+        None if keep_empty => SheetRect {
+            x: 0,
+            y: 0,
+            w: 1,
+            h: 1,
+        },
+        None => return Ok(None),
+    };
+
+    let mut pixels = Vec::new();
+    for y in rect.y..rect.y + rect.h {
+        for x in rect.x..rect.x + rect.w {
+            let i = ((y * project.width + x) * 4) as usize;
+            let pixel = data[i..i + 4].try_into().unwrap();
+            match colors {
+                Some(colors) => pixels.push(colors.index(pixel)),
+                None => pixels.extend_from_slice(pixel),
+            }
+        }
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&pixels)?;
+
+    let mut chunk = Writer::default();
+    chunk.word(layer);
+    chunk.short(rect.x as i16);
+    chunk.short(rect.y as i16);
+    chunk.byte(255);
+    chunk.word(CEL_COMPRESSED);
+    chunk.short(0);
+    chunk.zeros(5);
+    chunk.word(rect.w as u16);
+    chunk.word(rect.h as u16);
+    chunk.bytes(&encoder.finish()?);
+    Ok(Some(chunk))
+}
+
+fn linked_cel_chunk(layer: u16, other: u16) -> Writer {
+    let mut chunk = Writer::default();
+    chunk.word(layer);
+    chunk.zeros(2 + 2);
+    chunk.byte(255);
+    chunk.word(CEL_LINKED);
+    chunk.short(0);
+    chunk.zeros(5);
+    chunk.word(other);
+    chunk
+}
+
+fn tags_chunk(tags: &[(String, u16, u16, TagDirection)]) -> Writer {
+    let mut chunk = Writer::default();
+    chunk.word(tags.len() as u16);
+    chunk.zeros(8);
+    for (name, from, to, direction) in tags {
+        chunk.word(*from);
+        chunk.word(*to);
+        chunk.byte(match direction {
+            TagDirection::Forward => 0,
+            TagDirection::Reverse => 1,
+            TagDirection::PingPong => 2,
+        });
+        // repeat forever, then the deprecated color and extra byte
+        chunk.word(0);
+        chunk.zeros(6 + 3 + 1);
+        chunk.string(name);
+    }
+    chunk
+}
+
+fn palette_chunk(palette: &[[u8; 4]]) -> Writer {
+    let mut chunk = Writer::default();
+    chunk.dword(palette.len() as u32);
+    chunk.dword(0);
+    chunk.dword(palette.len().saturating_sub(1) as u32);
+    chunk.zeros(8);
+    for color in palette {
+        chunk.word(0);
+        chunk.bytes(color);
+    }
+    chunk
+}
+
+/// write `project` as an aseprite file, every timeline is stacked into one.
+///
+/// the file is indexed if `indexed` is set and every color of the document is in the palette,
+/// otherwise it's rgba. durations and tags come from `timeline`.
+pub fn write_aseprite(
+    project: &Project,
+    timeline: usize,
+    indexed: bool,
+) -> Result<Vec<u8>, FileError> {
+    let (Ok(width), Ok(height)) = (u16::try_from(project.width), u16::try_from(project.height))
+    else {
+        return Err(FileError::InvalidSize);
+    };
+
+    let mut palette = project
+        .palette
+        .palette
+        .iter()
+        .map(|color| color.as_rgba_u8())
+        .collect::<Vec<_>>();
+    let colors = if indexed {
+        let colors = IndexedColors::new(project, &mut palette);
+        if colors.is_none() {
+            warn!("the document uses colors that aren't in the palette, writing rgba instead");
+        }
+        colors
+    } else {
+        None
+    };
+    let depth = if colors.is_some() { 8 } else { 32 };
+
+    let mut nodes = Vec::new();
+    for data in &project.timelines {
+        ordered_nodes(project, &data.layers, 0, data.frame_offset, &mut nodes);
+    }
+    let frame_count = nodes
+        .iter()
+        .filter_map(|(node, _, offset)| match node {
+            NodeData::Layer(i) => Some((&project.layers[*i], *offset)),
+            NodeData::Group { .. } => None,
+        })
+        .flat_map(|(layer, offset)| {
+            let frames = layer.frames.iter().map(|(frame, _)| *frame);
+            let links = layer.links.iter().map(|(frame, _)| *frame);
+            frames.chain(links).map(move |frame| frame + offset + 1)
+        })
+        .max()
+        .unwrap_or(1)
+        .clamp(1, u16::MAX as i32);
+
+    let (durations, tags) = match project.timelines.get(timeline) {
+        Some(data) => {
+            let durations = (0..frame_count)
+                .map(|frame| {
+                    usize::try_from(frame - data.frame_offset)
+                        .ok()
+                        .and_then(|i| data.durations.get(i))
+                        .copied()
+                        .unwrap_or(DEFAULT_DURATION)
+                })
+                .collect::<Vec<_>>();
+            let tags = data
+                .tags
+                .iter()
+                .map(|tag| {
+                    (
+                        tag.from + data.frame_offset,
+                        tag.to + data.frame_offset,
+                        tag,
+                    )
+                })
+                .filter(|(from, to, _)| *from < frame_count && *to >= 0)
+                .map(|(from, to, tag)| {
+                    (
+                        tag.name.clone(),
+                        from.max(0) as u16,
+                        to.min(frame_count - 1) as u16,
+                        tag.direction,
+                    )
+                })
+                .collect::<Vec<_>>();
+            (durations, tags)
+        }
+        None => (vec![DEFAULT_DURATION; frame_count as usize], Vec::new()),
+    };
+
+    // the cels of every frame, linked cels point at the frame that stores the pixels
+    let mut frames = (0..frame_count).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (layer_index, (node, _, offset)) in nodes.iter().enumerate() {
+        let NodeData::Layer(i) = node else {
+            continue;
+        };
+        let layer = &project.layers[*i];
+        let targets = layer
+            .links
+            .iter()
+            .map(|(_, other)| *other)
+            .collect::<Vec<_>>();
+        for (frame, data) in &layer.frames {
+            let Some(cels) = frames.get_mut((frame + offset) as usize) else {
+                continue;
+            };
+            let keep_empty = targets.contains(frame);
+            if let Some(chunk) = image_cel_chunk(
+                project,
+                layer_index as u16,
+                data,
+                colors.as_ref(),
+                keep_empty,
+            )? {
+                cels.push(chunk);
+            }
+        }
+        for (frame, other) in &layer.links {
+            let (frame, other) = (frame + offset, other + offset);
+            if other < 0 {
+                continue;
+            }
+            if let Some(cels) = frames.get_mut(frame as usize) {
+                cels.push(linked_cel_chunk(layer_index as u16, other as u16));
+            }
+        }
+    }
+
+    let mut file = Writer::default();
+    // the size is filled in at the end
+    file.dword(0);
+    file.word(FILE_MAGIC);
+    file.word(frame_count as u16);
+    file.word(width);
+    file.word(height);
+    file.word(depth);
+    file.dword(FLAG_LAYER_OPACITY);
+    file.word(DEFAULT_DURATION as u16);
+    file.zeros(4 + 4);
+    file.byte(colors.as_ref().map_or(0, |colors| colors.transparent));
+    file.zeros(3);
+    file.word(if palette.len() >= 256 {
+        0
+    } else {
+        palette.len() as u16
+    });
+    // square pixels, no grid
+    file.byte(1);
+    file.byte(1);
+    file.zeros(2 + 2 + 2 + 2 + 84);
+
+    for (frame, cels) in frames.into_iter().enumerate() {
+        let mut chunks = Vec::new();
+        if frame == 0 {
+            chunks.push((CHUNK_PALETTE, palette_chunk(&palette)));
+            for (node, level, _) in &nodes {
+                chunks.push((CHUNK_LAYER, layer_chunk(project, *node, *level)));
+            }
+            if !tags.is_empty() {
+                chunks.push((CHUNK_TAGS, tags_chunk(&tags)));
+            }
+        }
+        chunks.extend(cels.into_iter().map(|cel| (CHUNK_CEL, cel)));
+
+        let mut body = Writer::default();
+        for (kind, chunk) in chunks.iter_mut() {
+            body.chunk(*kind, std::mem::take(chunk));
+        }
+
+        file.dword(body.0.len() as u32 + 16);
+        file.word(FRAME_MAGIC);
+        file.word(chunks.len().min(0xFFFF) as u16);
+        file.word(durations[frame].min(u16::MAX as u32) as u16);
+        file.zeros(2);
+        file.dword(chunks.len() as u32);
+        file.bytes(&body.0);
+    }
+
+    let size = file.0.len() as u32;
+    file.0[..4].copy_from_slice(&size.to_le_bytes());
+    Ok(file.0)
+}
+
+pub fn save_aseprite(
+    path: impl AsRef<Path>,
+    project: &Project,
+    timeline: usize,
+    indexed: bool,
+) -> Result<(), FileError> {
+    fs::write(path, write_aseprite(project, timeline, indexed)?)?;
+    Ok(())
+}
+
+/// [ctrl + shift + s] : export the document as an `.aseprite` file, indexed if the
+/// palette is
+pub fn export_aseprite(
+    input: Res<Input<KeyCode>>,
+    canvas: Res<Canvas>,
+    layers: Query<(Entity, &Layer, &Transform), Without<Detached>>,
+    groups: Query<(Entity, &LayerGroup, Option<&Children>), Without<Detached>>,
+    timelines: Query<(Entity, &Timeline)>,
+    images: Res<Assets<Image>>,
+    palette: Res<ColorPalette>,
+    path: Res<DocumentPath>,
+) {
+    if !(input.pressed(KeyCode::LControl)
+        && input.pressed(KeyCode::LShift)
+        && input.just_pressed(KeyCode::S))
+    {
+        return;
+    }
+
+    let project = Project::new(&canvas, &layers, &groups, &timelines, &images, &palette);
+    // durations and tags come from the timeline of the active layer
+    let timeline = layers
+        .get(canvas.layer_id)
+        .ok()
+        .and_then(|(_, layer, _)| layer.timeline_id)
+        .and_then(|id| timelines.iter().position(|(other, _)| other == id))
+        .unwrap_or(0);
+    let indexed = matches!(palette.color_state, ColorState::Indexed { .. });
+
+    let path = path.0.with_extension("aseprite");
+    match save_aseprite(&path, &project, timeline, indexed) {
+        Ok(()) => info!("exported {:?}", path),
+        Err(err) => error!("failed to export {:?}: {}", path, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAR: [u8; 4] = [0; 4];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn layer(name: &str, frames: Vec<(i32, [[u8; 4]; 6])>, links: Vec<(i32, i32)>) -> LayerData {
+        LayerData {
+            name: String::from(name),
+            z: 0.0,
+            visible: true,
+            locked: false,
+            opacity: 1.0,
+            blend_mode: BlendMode::Normal,
+            onion_skin: true,
+            timeline: Some(0),
+            frames: frames
+                .into_iter()
+                .map(|(frame, pixels)| (frame, pixels.concat()))
+                .collect(),
+            links,
+        }
+    }
+
+    /// a 3 x 2 document with a layer and a group of two layers, bottom first.
+    fn project() -> Project {
+        Project {
+            width: 3,
+            height: 2,
+            active_layer: 2,
+            layers: vec![
+                // frame 1 is linked to frame 0
+                layer(
+                    "Background",
+                    vec![
+                        (0, [RED, RED, RED, GREEN, GREEN, GREEN]),
+                        (2, [BLUE, CLEAR, CLEAR, CLEAR, CLEAR, BLUE]),
+                    ],
+                    vec![(1, 0)],
+                ),
+                layer(
+                    "Outline",
+                    vec![
+                        (0, [CLEAR, GREEN, CLEAR, CLEAR, CLEAR, CLEAR]),
+                        (1, [CLEAR, CLEAR, CLEAR, CLEAR, BLUE, CLEAR]),
+                    ],
+                    Vec::new(),
+                ),
+                LayerData {
+                    visible: false,
+                    locked: true,
+                    opacity: 51.0 / 255.0,
+                    blend_mode: BlendMode::Multiply,
+                    ..layer(
+                        "Shading",
+                        vec![(2, [CLEAR, CLEAR, RED, CLEAR, CLEAR, CLEAR])],
+                        Vec::new(),
+                    )
+                },
+            ],
+            groups: vec![GroupData {
+                name: String::from("Character"),
+                visible: true,
+                opacity: 1.0,
+                blend_mode: BlendMode::Normal,
+                collapsed: true,
+                children: vec![NodeData::Layer(1), NodeData::Layer(2)],
+            }],
+            timelines: vec![TimelineData {
+                layers: vec![NodeData::Layer(0), NodeData::Group { group: 0 }],
+                frame_offset: 0,
+                durations: vec![100, 250, 40],
+                tags: vec![
+                    Tag {
+                        name: String::from("idle"),
+                        from: 0,
+                        to: 1,
+                        direction: TagDirection::PingPong,
+                    },
+                    Tag {
+                        name: String::from("blink"),
+                        from: 2,
+                        to: 2,
+                        direction: TagDirection::Reverse,
+                    },
+                ],
+            }],
+            palette: ColorPalette {
+                palette: [CLEAR, RED, GREEN, BLUE]
+                    .iter()
+                    .map(|[r, g, b, a]| Color::rgba_u8(*r, *g, *b, *a))
+                    .collect(),
+                color_state: ColorState::default(),
+            },
+        }
+    }
+
+    /// `Layer(i)` or `Group(i)`, [`NodeData`] can't be compared.
+    fn nodes(nodes: &[NodeData]) -> Vec<(bool, usize)> {
+        nodes
+            .iter()
+            .map(|node| match node {
+                NodeData::Layer(i) => (false, *i),
+                NodeData::Group { group } => (true, *group),
+            })
+            .collect()
+    }
+
+    fn assert_same(read: &Project, written: &Project) {
+        assert_eq!((read.width, read.height), (written.width, written.height));
+        assert_eq!(read.layers.len(), written.layers.len());
+        for (a, b) in read.layers.iter().zip(&written.layers) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(
+                (a.visible, a.locked, a.opacity, a.blend_mode),
+                (b.visible, b.locked, b.opacity, b.blend_mode),
+                "{}",
+                a.name
+            );
+            assert_eq!(a.frames, b.frames, "{}", a.name);
+            assert_eq!(a.links, b.links, "{}", a.name);
+        }
+
+        assert_eq!(read.groups.len(), written.groups.len());
+        for (a, b) in read.groups.iter().zip(&written.groups) {
+            assert_eq!(
+                (&a.name, a.visible, a.opacity, a.blend_mode, a.collapsed),
+                (&b.name, b.visible, b.opacity, b.blend_mode, b.collapsed)
+            );
+            assert_eq!(nodes(&a.children), nodes(&b.children));
+        }
+
+        let (a, b) = (&read.timelines[0], &written.timelines[0]);
+        assert_eq!(nodes(&a.layers), nodes(&b.layers));
+        assert_eq!(a.durations, b.durations);
+        let tags = |tags: &[Tag]| {
+            tags.iter()
+                .map(|tag| (tag.name.clone(), tag.from, tag.to, tag.direction))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tags(&a.tags), tags(&b.tags));
+
+        let colors = |palette: &ColorPalette| {
+            palette
+                .palette
+                .iter()
+                .map(|color| color.as_rgba_u8())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(colors(&read.palette), colors(&written.palette));
+    }
+
+    #[test]
+    fn round_trip_rgba() {
+        let project = project();
+        let data = write_aseprite(&project, 0, false).unwrap();
+        // 32 bits per pixel
+        assert_eq!(u16::from_le_bytes([data[12], data[13]]), 32);
+
+        let read = parse_aseprite(&data).unwrap();
+        assert_same(&read, &project);
+        assert!(matches!(read.palette.color_state, ColorState::Color { .. }));
+    }
+
+    #[test]
+    fn round_trip_indexed() {
+        let project = project();
+        let data = write_aseprite(&project, 0, true).unwrap();
+        // every color is in the palette, so it's 8 bits per pixel
+        assert_eq!(u16::from_le_bytes([data[12], data[13]]), 8);
+
+        let read = parse_aseprite(&data).unwrap();
+        assert_same(&read, &project);
+        assert!(matches!(
+            read.palette.color_state,
+            ColorState::Indexed { .. }
+        ));
+    }
+
+    /// `data` with a chunk added to the start of the first frame.
+    fn with_chunk(data: &[u8], kind: u16, chunk: Writer) -> Vec<u8> {
+        let mut body = Writer::default();
+        body.chunk(kind, chunk);
+        let mut data = data.to_vec();
+        // the header is 128 bytes, followed by the 16 bytes of the frame header
+        data.splice(144..144, body.0.iter().copied());
+        let grow = |data: &mut Vec<u8>, at: usize, by: usize| {
+            let value = u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) + by as u32;
+            data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        grow(&mut data, 0, body.0.len());
+        grow(&mut data, 128, body.0.len());
+        grow(&mut data, 140, 1);
+        data[134] += 1;
+        data
+    }
+
+    fn cel(kind: u16, width: u16, height: u16, pixels: &[u8]) -> Writer {
+        let mut chunk = Writer::default();
+        chunk.word(0);
+        chunk.zeros(2 + 2);
+        chunk.byte(255);
+        chunk.word(kind);
+        chunk.zeros(2 + 5);
+        chunk.word(width);
+        chunk.word(height);
+        chunk.bytes(pixels);
+        chunk
+    }
+
+    fn compressed(pixels: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(pixels).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn unsupported_chunks_are_skipped() {
+        let project = project();
+        let data = write_aseprite(&project, 0, false).unwrap();
+        // a slice chunk
+        let mut slice = Writer::default();
+        slice.zeros(32);
+        let data = with_chunk(&data, 0x2022, slice);
+
+        let read = parse_aseprite(&data).unwrap();
+        assert_same(&read, &project);
+    }
+
+    #[test]
+    fn broken_files_are_refused() {
+        let data = write_aseprite(&project(), 0, false).unwrap();
+        assert!(parse_aseprite(&data[..data.len() - 10]).is_err());
+        assert!(parse_aseprite(&data[..100]).is_err());
+
+        let mut wrong = data.clone();
+        wrong[4] = 0;
+        assert!(parse_aseprite(&wrong).is_err());
+
+        let mut depth = data.clone();
+        depth[12] = 24;
+        assert!(parse_aseprite(&depth).is_err());
+    }
+
+    #[test]
+    fn cels_bigger_than_the_sprite_are_refused() {
+        let data = write_aseprite(&project(), 0, false).unwrap();
+        // the sprite is 3 x 2, the pixels don't have to be there for it to fail
+        let huge = with_chunk(&data, CHUNK_CEL, cel(CEL_RAW, u16::MAX, u16::MAX, &[]));
+        assert!(parse_aseprite(&huge).is_err());
+        let huge = with_chunk(&data, CHUNK_CEL, cel(CEL_COMPRESSED, 4, 4, &[]));
+        assert!(parse_aseprite(&huge).is_err());
+    }
+
+    #[test]
+    fn compressed_cels_are_cut_to_their_size() {
+        let data = write_aseprite(&project(), 0, false).unwrap();
+
+        // far more than the 2 pixels the cel has room for
+        let pixels = compressed(&vec![255; 1 << 20]);
+        let cel_chunk = cel(CEL_COMPRESSED, 2, 1, &pixels);
+        let mut chunk = Reader::new(&cel_chunk.0);
+        let Some((0, AseCel::Image { pixels, .. })) = read_cel(&mut chunk, 4, 24).unwrap() else {
+            panic!("expected an image cel");
+        };
+        assert_eq!(pixels, [255; 8]);
+
+        // and less than it needs
+        let short = with_chunk(
+            &data,
+            CHUNK_CEL,
+            cel(CEL_COMPRESSED, 2, 1, &compressed(&[255; 4])),
+        );
+        assert!(parse_aseprite(&short).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{app::AppExit, prelude::*, tasks::IoTaskPool};
+
+use crate::{
+    canvas::Canvas,
+    group::{Detached, LayerGroup},
+    layer::Layer,
+    timeline::Timeline,
+    tools::MoveState,
+    ColorPalette,
+};
+
+use super::{
+    project::{load_project, save_project, Project},
+    DocumentPath, FileError,
+};
+
+/// where the document is written while sprinkle is running, removed again on a clean exit.
+pub fn recovery_dir() -> PathBuf {
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".local/share/sprinkle/recovery"),
+        None => std::env::temp_dir().join("sprinkle-recovery"),
+    }
+}
+
+/// the autosaved project and a text file with the path of the document it belongs to.
+fn recovery_files(dir: &Path) -> (PathBuf, PathBuf) {
+    (dir.join("recovery.sprinkle"), dir.join("recovery.path"))
+}
+
+#[derive(Resource)]
+pub struct Autosave {
+    pub enabled: bool,
+    pub dir: PathBuf,
+    timer: Timer,
+    /// the document changed since it was last written
+    dirty: bool,
+    /// a recovery file left behind by a crash, autosave waits until it's restored or discarded
+    recovery: Option<PathBuf>,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Autosave {
+            enabled: true,
+            dir: recovery_dir(),
+            timer: Timer::new(Duration::from_secs(60), TimerMode::Repeating),
+            dirty: false,
+            recovery: None,
+        }
+    }
+}
+
+impl Autosave {
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.timer.set_duration(interval);
+        self.timer.reset();
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn remove_files(&self) {
+        let (project, path) = recovery_files(&self.dir);
+        let _ = fs::remove_file(project);
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// write the project to the recovery dir, the old file is only replaced once the new one is
+/// complete.
+pub fn save_recovery(dir: &Path, project: &Project, document: &Path) -> Result<(), FileError> {
+    fs::create_dir_all(dir)?;
+    let (project_path, path_path) = recovery_files(dir);
+    let tmp = project_path.with_extension("tmp");
+    save_project(&tmp, project)?;
+    fs::rename(&tmp, &project_path)?;
+    fs::write(path_path, document.to_string_lossy().as_bytes())?;
+    Ok(())
+}
+
+/// look for a recovery file left behind by a crash.
+pub fn check_recovery(mut autosave: ResMut<Autosave>) {
+    let (project_path, path_path) = recovery_files(&autosave.dir);
+    if !project_path.exists() {
+        return;
+    }
+
+    let document = fs::read_to_string(path_path).unwrap_or_default();
+    warn!(
+        "sprinkle didn't close properly last time, a recovery of {:?} was found. \
+        press [ctrl + r] to restore it or [ctrl + shift + r] to throw it away",
+        document
+    );
+    autosave.recovery = Some(project_path);
+}
+
+/// mark the document as changed when a layer, group, timeline, image or the palette changed.
+pub fn track_changes(
+    mut autosave: ResMut<Autosave>,
+    canvas: Res<Canvas>,
+    palette: Res<ColorPalette>,
+    layers: Query<&Layer>,
+    changed: Query<(), Or<(Changed<Layer>, Changed<LayerGroup>, Changed<Timeline>)>>,
+    mut removed_layers: RemovedComponents<Layer>,
+    mut removed_groups: RemovedComponents<LayerGroup>,
+    mut events: EventReader<AssetEvent<Image>>,
+    mut size: Local<UVec2>,
+) {
+    let canvas_size = UVec2::new(canvas.width, canvas.height);
+    let mut dirty = *size != canvas_size
+        || palette.is_changed()
+        || !changed.is_empty()
+        || removed_layers.iter().count() > 0
+        || removed_groups.iter().count() > 0;
+    *size = canvas_size;
+    for ev in events.iter() {
+        if let AssetEvent::Modified { handle } = ev {
+            dirty |= layers
+                .iter()
+                .any(|layer| layer.frames.values().any(|h| h == handle));
+        }
+    }
+
+    if dirty && !autosave.dirty {
+        autosave.dirty = true;
+    }
+}
+
+/// write the document to the recovery dir every so often, if it changed.
+///
+/// the project is built right away and written on the io task pool.
+pub fn autosave(
+    time: Res<Time>,
+    mut autosave: ResMut<Autosave>,
+    canvas: Res<Canvas>,
+    layers: Query<(Entity, &Layer, &Transform), Without<Detached>>,
+    groups: Query<(Entity, &LayerGroup, Option<&Children>), Without<Detached>>,
+    timelines: Query<(Entity, &Timeline)>,
+    images: Res<Assets<Image>>,
+    palette: Res<ColorPalette>,
+    path: Res<DocumentPath>,
+    move_state: Res<MoveState>,
+) {
+    if !autosave.enabled || autosave.recovery.is_some() {
+        return;
+    }
+    if !autosave.timer.tick(time.delta()).just_finished() || !autosave.dirty {
+        return;
+    }
+    // the half moved pixels aren't saved, it's tried again on the next tick
+    if move_state.is_floating() {
+        return;
+    }
+    autosave.dirty = false;
+
+    let project = Project::new(&canvas, &layers, &groups, &timelines, &images, &palette);
+    let dir = autosave.dir.clone();
+    let document = path.0.clone();
+    IoTaskPool::get()
+        .spawn(async move {
+            match save_recovery(&dir, &project, &document) {
+                Ok(()) => info!("autosaved {:?}", document),
+                Err(err) => error!("failed to autosave {:?}: {}", document, err),
+            }
+        })
+        .detach();
+}
+
+/// [ctrl + r] : restore the document found in the recovery dir
+/// [ctrl + shift + r] : throw away the document found in the recovery dir
+pub fn recovery_input(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut autosave: ResMut<Autosave>,
+    mut images: ResMut<Assets<Image>>,
+    document: Query<
+        Entity,
+        (
+            Or<(With<Layer>, With<LayerGroup>, With<Timeline>)>,
+            Without<Parent>,
+        ),
+    >,
+) {
+    if autosave.recovery.is_none()
+        || !(input.pressed(KeyCode::LControl) && input.just_pressed(KeyCode::R))
+    {
+        return;
+    }
+    let recovery = autosave.recovery.take().unwrap();
+
+    if input.pressed(KeyCode::LShift) {
+        autosave.remove_files();
+        info!("threw away the recovery file");
+        return;
+    }
+
+    let project = match load_project(&recovery) {
+        Ok(project) => project,
+        Err(err) => {
+            error!("failed to restore {:?}: {}", recovery, err);
+            return;
+        }
+    };
+    for id in &document {
+        commands.entity(id).despawn_recursive();
+    }
+    let canvas = project.spawn(&mut commands, &mut images);
+    commands.insert_resource(canvas);
+
+    let (_, path_path) = recovery_files(&autosave.dir);
+    if let Ok(document) = fs::read_to_string(path_path) {
+        commands.insert_resource(DocumentPath(PathBuf::from(document)));
+    }
+    // it's only written to the recovery dir, so it still counts as unsaved
+    autosave.dirty = true;
+    info!("restored the document from {:?}", recovery);
+}
+
+/// a clean exit doesn't leave a recovery file behind.
+pub fn remove_recovery_on_exit(autosave: Res<Autosave>, mut exit: EventReader<AppExit>) {
+    if exit.iter().count() > 0 && autosave.recovery.is_none() {
+        autosave.remove_files();
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::Path,
+};
+
+use ::gif::{DisposalMethod, Encoder, Frame, Repeat};
+use bevy::prelude::*;
+use color_quant::NeuQuant;
+
+use crate::{
+    canvas::Canvas,
+    composite::LayerTree,
+    timeline::{GlobalTimeline, DEFAULT_DURATION},
+    ColorPalette, ColorState,
+};
+
+use super::{DocumentPath, FileError};
+
+/// how animations are exported as gif.
+#[derive(Resource)]
+pub struct GifSettings {
+    /// every pixel is drawn as a `scale` x `scale` square
+    pub scale: u32,
+    /// the color stored in transparent pixels, viewers that ignore transparency show it.
+    /// without a key the frames are drawn on white
+    pub transparency_key: Option<[u8; 3]>,
+}
+
+impl Default for GifSettings {
+    fn default() -> Self {
+        GifSettings {
+            scale: 1,
+            transparency_key: Some([255, 0, 255]),
+        }
+    }
+}
+
+impl GifSettings {
+    /// parse a key like `ff00ff`, or `none`
+    pub fn parse_key(key: &str) -> Option<Option<[u8; 3]>> {
+        if key.eq_ignore_ascii_case("none") {
+            return Some(None);
+        }
+        let key = key.trim_start_matches('#');
+        if key.len() != 6 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let [_, r, g, b] = u32::from_str_radix(key, 16).ok()?.to_be_bytes();
+        Some(Some([r, g, b]))
+    }
+}
+
+/// gif only has on / off transparency, pixels that are less than half opaque become `None`.
+///
+/// without a transparency key everything is drawn on white instead.
+fn to_rgb(data: &[u8], keyed: bool) -> Vec<Option<[u8; 3]>> {
+    data.array_chunks::<4>()
+        .map(|&[r, g, b, a]| {
+            if keyed {
+                (a >= 128).then_some([r, g, b])
+            } else {
+                let over_white =
+                    |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+                Some([over_white(r), over_white(g), over_white(b)])
+            }
+        })
+        .collect()
+}
+
+/// up to 255 colors for the frames, one index is left for transparency.
+///
+/// the document palette is used as is, otherwise the colors of the frames are used
+/// if there are few enough of them, or reduced with neuquant if not.
+fn build_palette(frames: &[Vec<Option<[u8; 3]>>], document: Option<&[Color]>) -> Vec<[u8; 3]> {
+    if let Some(colors) = document.filter(|colors| !colors.is_empty()) {
+        return colors
+            .iter()
+            .take(255)
+            .map(|color| {
+                let [r, g, b, _] = color.as_rgba_u8();
+                [r, g, b]
+            })
+            .collect();
+    }
+
+    let unique = frames
+        .iter()
+        .flatten()
+        .flatten()
+        .copied()
+        .collect::<HashSet<_>>();
+    if unique.len() <= 255 {
+        let mut palette = unique.into_iter().collect::<Vec<_>>();
+        palette.sort();
+        if palette.is_empty() {
+            palette.push([0, 0, 0]);
+        }
+        return palette;
+    }
+
+    let pixels = frames
+        .iter()
+        .flatten()
+        .flatten()
+        .flat_map(|&[r, g, b]| [r, g, b, 255])
+        .collect::<Vec<_>>();
+    NeuQuant::new(10, 255, &pixels)
+        .color_map_rgb()
+        .array_chunks::<3>()
+        .copied()
+        .collect()
+}
+
+fn nearest(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    let distance = |other: &[u8; 3]| -> i32 {
+        other
+            .iter()
+            .zip(color)
+            .map(|(a, b)| (*a as i32 - b as i32).pow(2))
+            .sum()
+    };
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, other)| distance(other))
+        .map(|(i, _)| i as u8)
+        .unwrap()
+}
+
+/// repeat every index `scale` times in both directions.
+fn upscale(indices: &[u8], width: u32, scale: u32) -> Vec<u8> {
+    if scale == 1 {
+        return indices.to_vec();
+    }
+    indices
+        .chunks(width as usize)
+        .flat_map(|row| {
+            let row = row
+                .iter()
+                .flat_map(|i| std::iter::repeat(*i).take(scale as usize))
+                .collect::<Vec<_>>();
+            std::iter::repeat(row).take(scale as usize).flatten()
+        })
+        .collect()
+}
+
+/// write `frames` (rgba8 data and duration in milliseconds) as a looping gif.
+///
+/// the colors are quantized to `palette`, or to the colors of the frames if `None`.
+pub fn save_gif(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    frames: &[(Vec<u8>, u32)],
+    palette: Option<&[Color]>,
+    settings: &GifSettings,
+) -> Result<(), FileError> {
+    let scale = settings.scale.max(1);
+    let (Ok(scaled_width), Ok(scaled_height)) =
+        (u16::try_from(width * scale), u16::try_from(height * scale))
+    else {
+        return Err(FileError::InvalidSize);
+    };
+    if frames
+        .iter()
+        .any(|(data, _)| data.len() != (width * height * 4) as usize)
+    {
+        return Err(FileError::InvalidSize);
+    }
+
+    let keyed = settings.transparency_key.is_some();
+    let rgb = frames
+        .iter()
+        .map(|(data, _)| to_rgb(data, keyed))
+        .collect::<Vec<_>>();
+    let colors = build_palette(&rgb, palette);
+    let transparent = colors.len() as u8;
+
+    let mut global_palette = colors.iter().flatten().copied().collect::<Vec<_>>();
+    if let Some(key) = settings.transparency_key {
+        global_palette.extend(key);
+    }
+
+    let mut encoder = Encoder::new(
+        File::create(path)?,
+        scaled_width,
+        scaled_height,
+        &global_palette,
+    )?;
+    encoder.set_repeat(Repeat::Infinite)?;
+
+    let mut cache = HashMap::new();
+    for (pixels, (_, duration)) in rgb.iter().zip(frames) {
+        let indices = pixels
+            .iter()
+            .map(|pixel| match pixel {
+                Some(color) => *cache
+                    .entry(*color)
+                    .or_insert_with(|| nearest(&colors, *color)),
+                None => transparent,
+            })
+            .collect::<Vec<_>>();
+
+        let frame = Frame {
+            width: scaled_width,
+            height: scaled_height,
+            // in hundredths of a second
+            delay: ((duration + 5) / 10).clamp(1, u16::MAX as u32) as u16,
+            dispose: DisposalMethod::Background,
+            transparent: keyed.then_some(transparent),
+            buffer: Cow::Owned(upscale(&indices, width, scale)),
+            ..Default::default()
+        };
+        encoder.write_frame(&frame)?;
+    }
+    Ok(())
+}
+
+/// [ctrl + alt + e] : export the frame range, or the played tag, as an animated gif
+/// [ctrl + alt + shift + e] : export the tag at the current frame as an animated gif
+pub fn export_gif(
+    input: Res<Input<KeyCode>>,
+    canvas: Res<Canvas>,
+    tree: LayerTree,
+    global_timeline: Res<GlobalTimeline>,
+    images: Res<Assets<Image>>,
+    palette: Res<ColorPalette>,
+    settings: Res<GifSettings>,
+    path: Res<DocumentPath>,
+) {
+    if !(input.pressed(KeyCode::LControl)
+        && input.pressed(KeyCode::LAlt)
+        && input.just_pressed(KeyCode::E))
+    {
+        return;
+    }
+
+    // durations and tags come from the timeline of the active layer
+    let timeline = tree
+        .layers
+        .get(canvas.layer_id)
+        .ok()
+        .and_then(|(layer, _)| tree.timelines.get(layer.timeline_id?).ok());
+
+    let (path, sequence) = if input.pressed(KeyCode::LShift) {
+        let tag = timeline.and_then(|timeline| {
+            let local = timeline.local_frame(global_timeline.current_frame());
+            Some((timeline, &timeline.tags()[timeline.tag_at(local)?]))
+        });
+        let Some((timeline, tag)) = tag else {
+            warn!("there's no tag at the current frame");
+            return;
+        };
+
+        let stem = path.0.file_stem().unwrap_or_default().to_string_lossy();
+        let sequence = tag
+            .sequence()
+            .into_iter()
+            .map(|frame| frame + timeline.frame_offset())
+            .collect();
+        (
+            path.0.with_file_name(format!("{stem}-{}.gif", tag.name)),
+            sequence,
+        )
+    } else {
+        (path.0.with_extension("gif"), global_timeline.sequence())
+    };
+
+    let frames = sequence
+        .into_iter()
+        .map(|frame| {
+            let duration = timeline.map_or(DEFAULT_DURATION, |timeline| {
+                timeline.duration(timeline.local_frame(frame))
+            });
+            (
+                tree.flatten(canvas.width, canvas.height, Some(frame), &images),
+                duration,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // indexed documents keep their palette
+    let colors = match palette.color_state {
+        ColorState::Indexed { .. } => Some(palette.palette.as_slice()),
+        ColorState::Color { .. } => None,
+    };
+
+    match save_gif(
+        &path,
+        canvas.width,
+        canvas.height,
+        &frames,
+        colors,
+        &settings,
+    ) {
+        Ok(()) => info!("exported {} frames to {:?}", frames.len(), path),
+        Err(err) => error!("failed to export {:?}: {}", path, err),
+    }
+}
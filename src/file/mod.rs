@@ -0,0 +1,81 @@
+use std::{fmt, path::PathBuf};
+
+use bevy::prelude::*;
+
+pub mod aseprite;
+pub mod autosave;
+pub mod gif;
+pub mod png;
+pub mod project;
+pub mod sheet;
+
+/// path of the current document, exports are written next to it.
+#[derive(Resource)]
+pub struct DocumentPath(pub PathBuf);
+
+impl Default for DocumentPath {
+    fn default() -> Self {
+        DocumentPath(PathBuf::from("untitled.png"))
+    }
+}
+
+#[derive(Debug)]
+pub enum FileError {
+    Io(std::io::Error),
+    Image(::image::ImageError),
+    Serde(serde_json::Error),
+    Gif(::gif::EncodingError),
+    /// the pixel data does not match the given width and height
+    InvalidSize,
+    /// the file is not a valid sprinkle project
+    InvalidFormat,
+    /// the file was written by a newer version of sprinkle
+    UnsupportedVersion(u32),
+    /// the aseprite file is broken or uses something that can't be read
+    InvalidAseprite(String),
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::Io(err) => write!(f, "io error: {err}"),
+            FileError::Image(err) => write!(f, "image error: {err}"),
+            FileError::Serde(err) => write!(f, "serialization error: {err}"),
+            FileError::Gif(err) => write!(f, "gif error: {err}"),
+            FileError::InvalidSize => write!(f, "pixel data does not match the image size"),
+            FileError::InvalidFormat => write!(f, "not a valid sprinkle project"),
+            FileError::UnsupportedVersion(version) => write!(
+                f,
+                "project version {version} is newer than the supported version {}, please update sprinkle",
+                project::VERSION
+            ),
+            FileError::InvalidAseprite(msg) => write!(f, "invalid aseprite file: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl From<std::io::Error> for FileError {
+    fn from(err: std::io::Error) -> Self {
+        FileError::Io(err)
+    }
+}
+
+impl From<::image::ImageError> for FileError {
+    fn from(err: ::image::ImageError) -> Self {
+        FileError::Image(err)
+    }
+}
+
+impl From<serde_json::Error> for FileError {
+    fn from(err: serde_json::Error) -> Self {
+        FileError::Serde(err)
+    }
+}
+
+impl From<::gif::EncodingError> for FileError {
+    fn from(err: ::gif::EncodingError) -> Self {
+        FileError::Gif(err)
+    }
+}
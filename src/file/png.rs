@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use crate::{canvas::Canvas, composite::LayerTree};
+
+use super::{DocumentPath, FileError};
+
+pub fn save_png(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Result<(), FileError> {
+    let buffer =
+        ::image::RgbaImage::from_raw(width, height, data.to_vec()).ok_or(FileError::InvalidSize)?;
+    buffer.save_with_format(path, ::image::ImageFormat::Png)?;
+    Ok(())
+}
+
+/// load a png as an rgba8 image, the size is taken from the file.
+pub fn load_png(path: impl AsRef<Path>) -> Result<Image, FileError> {
+    let buffer = ::image::open(path)?.into_rgba8();
+    let (width, height) = buffer.dimensions();
+
+    let mut image = crate::image(width, height, Color::NONE);
+    image.data = buffer.into_raw();
+    Ok(image)
+}
+
+/// [ctrl + e] : export the flattened canvas
+/// [ctrl + shift + e] : export the active layer
+pub fn export_png(
+    input: Res<Input<KeyCode>>,
+    canvas: Res<Canvas>,
+    tree: LayerTree,
+    images: Res<Assets<Image>>,
+    path: Res<DocumentPath>,
+) {
+    if !(input.pressed(KeyCode::LControl)
+        && !input.pressed(KeyCode::LAlt)
+        && input.just_pressed(KeyCode::E))
+    {
+        return;
+    }
+
+    // the frame that's currently shown
+    let (path, data) = if input.pressed(KeyCode::LShift) {
+        let (_, texture) = tree.layers.get(canvas.layer_id).unwrap();
+        let data = match images.get(texture) {
+            Some(image) => image.data.clone(),
+            None => vec![0; (canvas.width * canvas.height * 4) as usize],
+        };
+
+        let stem = path.0.file_stem().unwrap_or_default().to_string_lossy();
+        (path.0.with_file_name(format!("{stem}-layer.png")), data)
+    } else {
+        (
+            path.0.with_extension("png"),
+            tree.flatten(canvas.width, canvas.height, None, &images),
+        )
+    };
+
+    match save_png(&path, canvas.width, canvas.height, &data) {
+        Ok(()) => info!("exported {:?}", path),
+        Err(err) => error!("failed to export {:?}: {}", path, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_keeps_rgba_bytes() {
+        let (width, height) = (3, 2);
+        let data = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 128],
+            [0, 0, 255, 1],
+            [12, 34, 56, 0],
+            [255, 255, 255, 254],
+            [7, 8, 9, 77],
+        ]
+        .concat();
+
+        let path = std::env::temp_dir().join("sprinkle-png-round-trip.png");
+        save_png(&path, width, height, &data).unwrap();
+        let image = load_png(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(image.texture_descriptor.size.width, width);
+        assert_eq!(image.texture_descriptor.size.height, height);
+        assert_eq!(image.data, data);
+    }
+}
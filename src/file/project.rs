@@ -0,0 +1,520 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+use bevy::{prelude::*, utils::HashMap};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    canvas::Canvas,
+    composite::BlendMode,
+    group::{Detached, GroupBundle, LayerGroup},
+    layer::{Layer, LayerBundle},
+    timeline::{Tag, Timeline},
+    tools::MoveState,
+    ColorPalette, History,
+};
+
+use super::{DocumentPath, FileError};
+
+const MAGIC: &[u8; 8] = b"SPRINKLE";
+
+/// bump this whenever the layout of [`Project`] changes.
+///
+/// - 2: layer groups, frame durations and tags, the onion skin layer setting and linked
+///   cels. the history isn't stored anymore, it refers to entities of the session it was
+///   made in
+pub const VERSION: u32 = 2;
+
+/// everything needed to rebuild a document.
+#[derive(Serialize, Deserialize)]
+pub struct Project {
+    pub width: u32,
+    pub height: u32,
+    /// index into `layers`
+    pub active_layer: usize,
+    pub layers: Vec<LayerData>,
+    #[serde(default)]
+    pub groups: Vec<GroupData>,
+    pub timelines: Vec<TimelineData>,
+    pub palette: ColorPalette,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LayerData {
+    #[serde(default)]
+    pub name: String,
+    pub z: f32,
+    #[serde(default = "default_true")]
+    pub visible: bool,
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+    #[serde(default = "default_true")]
+    pub onion_skin: bool,
+    /// index into `Project::timelines`
+    pub timeline: Option<usize>,
+    pub frames: Vec<(i32, Vec<u8>)>,
+    /// linked cels, `(frame, other)` where `other` is a frame in `frames` with the same image
+    #[serde(default)]
+    pub links: Vec<(i32, i32)>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GroupData {
+    pub name: String,
+    pub visible: bool,
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+    pub collapsed: bool,
+    /// bottom first
+    pub children: Vec<NodeData>,
+}
+
+/// a layer or a group, layers are written as a plain index like in version 1.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(untagged)]
+pub enum NodeData {
+    /// index into `Project::layers`
+    Layer(usize),
+    /// index into `Project::groups`
+    Group { group: usize },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TimelineData {
+    /// the top level layers and groups, bottom first
+    pub layers: Vec<NodeData>,
+    pub frame_offset: i32,
+    /// milliseconds per frame
+    #[serde(default)]
+    pub durations: Vec<u32>,
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+}
+
+impl Project {
+    pub fn new(
+        canvas: &Canvas,
+        layers: &Query<(Entity, &Layer, &Transform), Without<Detached>>,
+        groups: &Query<(Entity, &LayerGroup, Option<&Children>), Without<Detached>>,
+        timelines: &Query<(Entity, &Timeline)>,
+        images: &Assets<Image>,
+        palette: &ColorPalette,
+    ) -> Self {
+        let timeline_indices = timelines
+            .iter()
+            .enumerate()
+            .map(|(i, (id, _))| (id, i))
+            .collect::<HashMap<_, _>>();
+        let layer_indices = layers
+            .iter()
+            .enumerate()
+            .map(|(i, (id, ..))| (id, i))
+            .collect::<HashMap<_, _>>();
+        let group_indices = groups
+            .iter()
+            .enumerate()
+            .map(|(i, (id, ..))| (id, i))
+            .collect::<HashMap<_, _>>();
+        let node = |id: &Entity| match layer_indices.get(id) {
+            Some(i) => Some(NodeData::Layer(*i)),
+            None => Some(NodeData::Group {
+                group: *group_indices.get(id)?,
+            }),
+        };
+
+        let layers_data = layers
+            .iter()
+            .map(|(_, layer, trans)| {
+                let mut sorted = layer.frames.iter().collect::<Vec<_>>();
+                sorted.sort_by_key(|(frame, _)| **frame);
+
+                // linked cels share a handle, only the first one stores the pixels
+                let mut stored = HashMap::new();
+                let mut frames = Vec::new();
+                let mut links = Vec::new();
+                for (frame, handle) in sorted {
+                    if let Some(other) = stored.get(&handle.id()) {
+                        links.push((*frame, *other));
+                    } else if let Some(image) = images.get(handle) {
+                        stored.insert(handle.id(), *frame);
+                        frames.push((*frame, image.data.clone()));
+                    }
+                }
+
+                LayerData {
+                    name: layer.name.clone(),
+                    z: trans.translation.z,
+                    visible: layer.visible,
+                    locked: layer.locked,
+                    opacity: layer.opacity,
+                    blend_mode: layer.blend_mode,
+                    onion_skin: layer.onion_skin,
+                    timeline: layer
+                        .timeline_id
+                        .and_then(|id| timeline_indices.get(&id).copied()),
+                    frames,
+                    links,
+                }
+            })
+            .collect();
+
+        let groups_data = groups
+            .iter()
+            .map(|(_, group, children)| GroupData {
+                name: group.name.clone(),
+                visible: group.visible,
+                opacity: group.opacity,
+                blend_mode: group.blend_mode,
+                collapsed: group.collapsed,
+                children: children
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|id| node(id))
+                    .collect(),
+            })
+            .collect();
+
+        let timelines_data = timelines
+            .iter()
+            .map(|(_, timeline)| TimelineData {
+                layers: timeline.layers().iter().filter_map(|id| node(id)).collect(),
+                frame_offset: timeline.frame_offset(),
+                durations: timeline.durations().to_vec(),
+                tags: timeline.tags().to_vec(),
+            })
+            .collect();
+
+        Project {
+            width: canvas.width,
+            height: canvas.height,
+            active_layer: layer_indices.get(&canvas.layer_id).copied().unwrap_or(0),
+            layers: layers_data,
+            groups: groups_data,
+            timelines: timelines_data,
+            palette: palette.clone(),
+        }
+    }
+
+    /// check that every index points at something, every frame has the right size and the
+    /// layers and groups form a tree.
+    pub fn is_valid(&self) -> bool {
+        let frame_len = (self.width * self.height * 4) as usize;
+
+        self.active_layer < self.layers.len()
+            && self.layers.iter().all(|layer| {
+                layer.timeline.map_or(true, |i| i < self.timelines.len())
+                    && layer.frames.iter().all(|(_, data)| data.len() == frame_len)
+                    && layer
+                        .links
+                        .iter()
+                        .all(|(_, other)| layer.frames.iter().any(|(frame, _)| frame == other))
+            })
+            && self.is_tree()
+    }
+
+    /// every layer and group is in at most one timeline or group, and every group can be
+    /// reached from a timeline, so there are no cycles.
+    fn is_tree(&self) -> bool {
+        let mut layers = vec![false; self.layers.len()];
+        let mut groups = vec![false; self.groups.len()];
+        let mut stack = self
+            .timelines
+            .iter()
+            .flat_map(|timeline| &timeline.layers)
+            .copied()
+            .collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            let seen = match node {
+                NodeData::Layer(i) => layers.get_mut(i),
+                NodeData::Group { group } => groups.get_mut(group),
+            };
+            let Some(seen) = seen else {
+                return false;
+            };
+            if std::mem::replace(seen, true) {
+                return false;
+            }
+            if let NodeData::Group { group } = node {
+                stack.extend(&self.groups[group].children);
+            }
+        }
+        groups.into_iter().all(|seen| seen)
+    }
+
+    /// spawn the layers and timelines of this project and return the canvas.
+    pub fn spawn(mut self, commands: &mut Commands, images: &mut Assets<Image>) -> Canvas {
+        // every layer needs a timeline, put the lonely ones in a new one
+        let grouped = self
+            .groups
+            .iter()
+            .flat_map(|group| &group.children)
+            .filter_map(|node| match node {
+                NodeData::Layer(i) => Some(*i),
+                NodeData::Group { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        let lonely = (0..self.layers.len())
+            .filter(|i| self.layers[*i].timeline.is_none() && !grouped.contains(i))
+            .collect::<Vec<_>>();
+        if !lonely.is_empty() {
+            let mut layers = lonely;
+            layers.sort_by(|a, b| self.layers[*a].z.total_cmp(&self.layers[*b].z));
+            for i in &layers {
+                self.layers[*i].timeline = Some(self.timelines.len());
+            }
+            self.timelines.push(TimelineData {
+                layers: layers.into_iter().map(NodeData::Layer).collect(),
+                frame_offset: 0,
+                durations: Vec::new(),
+                tags: Vec::new(),
+            });
+        }
+
+        let timeline_ids = self
+            .timelines
+            .iter()
+            .map(|_| commands.spawn_empty().id())
+            .collect::<Vec<_>>();
+        let group_ids = self
+            .groups
+            .iter()
+            .map(|_| commands.spawn_empty().id())
+            .collect::<Vec<_>>();
+
+        let layer_ids = self
+            .layers
+            .into_iter()
+            .map(|data| {
+                let mut frames = data
+                    .frames
+                    .into_iter()
+                    .map(|(frame, bytes)| {
+                        let mut image = crate::image(self.width, self.height, Color::NONE);
+                        image.data = bytes;
+                        (frame, images.add(image))
+                    })
+                    .collect::<HashMap<_, _>>();
+                for (frame, other) in data.links {
+                    let handle = frames[&other].clone();
+                    frames.insert(frame, handle);
+                }
+
+                commands
+                    .spawn(LayerBundle {
+                        transform: Transform::from_translation(Vec3::new(0.0, 0.0, data.z)),
+                        ..LayerBundle::new(Layer {
+                            name: data.name,
+                            frames,
+                            timeline_id: data.timeline.map(|i| timeline_ids[i]),
+                            visible: data.visible,
+                            locked: data.locked,
+                            opacity: data.opacity,
+                            blend_mode: data.blend_mode,
+                            onion_skin: data.onion_skin,
+                        })
+                    })
+                    .id()
+            })
+            .collect::<Vec<_>>();
+
+        let node = |node: NodeData| match node {
+            NodeData::Layer(i) => layer_ids[i],
+            NodeData::Group { group } => group_ids[group],
+        };
+
+        for (id, data) in group_ids.iter().zip(self.groups) {
+            let children = data.children.into_iter().map(node).collect::<Vec<_>>();
+            commands
+                .entity(*id)
+                .insert(GroupBundle {
+                    group: LayerGroup {
+                        name: data.name,
+                        visible: data.visible,
+                        opacity: data.opacity,
+                        blend_mode: data.blend_mode,
+                        collapsed: data.collapsed,
+                        timeline_id: None,
+                    },
+                    ..Default::default()
+                })
+                .push_children(&children);
+        }
+
+        for (id, data) in timeline_ids.into_iter().zip(self.timelines) {
+            let layers = data.layers.into_iter().map(node).collect();
+            commands.entity(id).insert(
+                Timeline::new(layers, data.frame_offset).with_animation(data.durations, data.tags),
+            );
+        }
+
+        commands.insert_resource(self.palette);
+        commands.insert_resource(History::default());
+
+        Canvas::new(self.width, self.height, layer_ids[self.active_layer])
+    }
+}
+
+pub fn save_project(path: impl AsRef<Path>, project: &Project) -> Result<(), FileError> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_le_bytes())?;
+
+    let mut encoder = ZlibEncoder::new(file, Compression::default());
+    serde_json::to_writer(&mut encoder, project)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+pub fn load_project(path: impl AsRef<Path>) -> Result<Project, FileError> {
+    let mut file = fs::File::open(path)?;
+
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(FileError::InvalidFormat);
+    }
+
+    let mut version = [0; 4];
+    file.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version > VERSION {
+        return Err(FileError::UnsupportedVersion(version));
+    }
+
+    // the history stored by version 1 is skipped
+    let project: Project = serde_json::from_reader(ZlibDecoder::new(file))?;
+    if !project.is_valid() {
+        return Err(FileError::InvalidFormat);
+    }
+    Ok(project)
+}
+
+/// [ctrl + s] : save the document as a `.sprinkle` project
+pub fn save_project_input(
+    input: Res<Input<KeyCode>>,
+    canvas: Res<Canvas>,
+    layers: Query<(Entity, &Layer, &Transform), Without<Detached>>,
+    groups: Query<(Entity, &LayerGroup, Option<&Children>), Without<Detached>>,
+    timelines: Query<(Entity, &Timeline)>,
+    images: Res<Assets<Image>>,
+    palette: Res<ColorPalette>,
+    mut path: ResMut<DocumentPath>,
+    move_state: Res<MoveState>,
+) {
+    if !(input.pressed(KeyCode::LControl)
+        && !input.pressed(KeyCode::LShift)
+        && input.just_pressed(KeyCode::S))
+    {
+        return;
+    }
+    if move_state.is_floating() {
+        warn!("put the moved pixels down before saving");
+        return;
+    }
+
+    let project = Project::new(&canvas, &layers, &groups, &timelines, &images, &palette);
+
+    let project_path = path.0.with_extension("sprinkle");
+    match save_project(&project_path, &project) {
+        Ok(()) => {
+            info!("saved {:?}", project_path);
+            path.0 = project_path;
+        }
+        Err(err) => error!("failed to save {:?}: {}", project_path, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a 1 x 1 project with `layers` layers, `groups` holds the children of each group.
+    fn project(layers: usize, groups: Vec<Vec<NodeData>>, top: Vec<NodeData>) -> Project {
+        Project {
+            width: 1,
+            height: 1,
+            active_layer: 0,
+            layers: (0..layers)
+                .map(|i| LayerData {
+                    name: format!("Layer {}", i + 1),
+                    z: i as f32,
+                    visible: true,
+                    locked: false,
+                    opacity: 1.0,
+                    blend_mode: BlendMode::Normal,
+                    onion_skin: true,
+                    timeline: Some(0),
+                    frames: vec![(0, vec![0; 4])],
+                    links: Vec::new(),
+                })
+                .collect(),
+            groups: groups
+                .into_iter()
+                .enumerate()
+                .map(|(i, children)| GroupData {
+                    name: format!("Group {}", i + 1),
+                    visible: true,
+                    opacity: 1.0,
+                    blend_mode: BlendMode::Normal,
+                    collapsed: false,
+                    children,
+                })
+                .collect(),
+            timelines: vec![TimelineData {
+                layers: top,
+                frame_offset: 0,
+                durations: Vec::new(),
+                tags: Vec::new(),
+            }],
+            palette: ColorPalette::default(),
+        }
+    }
+
+    use NodeData::Layer as L;
+
+    fn g(group: usize) -> NodeData {
+        NodeData::Group { group }
+    }
+
+    #[test]
+    fn nested_groups_are_valid() {
+        assert!(project(3, vec![vec![L(1), g(1)], vec![L(2)]], vec![L(0), g(0)]).is_valid());
+    }
+
+    #[test]
+    fn group_cycles_are_invalid() {
+        assert!(!project(1, vec![vec![L(0), g(0)]], vec![g(0)]).is_valid());
+        assert!(!project(1, vec![vec![g(1)], vec![L(0), g(0)]], vec![g(0)]).is_valid());
+        // a cycle nothing points at
+        assert!(!project(1, vec![vec![g(1)], vec![g(0)]], vec![L(0)]).is_valid());
+    }
+
+    #[test]
+    fn shared_children_are_invalid() {
+        assert!(!project(2, vec![vec![L(1)], vec![L(1)]], vec![L(0), g(0), g(1)]).is_valid());
+        assert!(!project(1, vec![vec![L(0)]], vec![L(0), g(0)]).is_valid());
+    }
+
+    #[test]
+    fn missing_children_are_invalid() {
+        assert!(!project(1, vec![vec![L(1)]], vec![L(0), g(0)]).is_valid());
+        assert!(!project(1, vec![vec![g(3)]], vec![L(0), g(0)]).is_valid());
+        assert!(!project(1, Vec::new(), vec![L(0), g(0)]).is_valid());
+    }
+}
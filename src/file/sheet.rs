@@ -0,0 +1,465 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use bevy::prelude::*;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::{
+    canvas::Canvas,
+    composite::LayerTree,
+    group::Detached,
+    layer::Layer,
+    timeline::{total_frame_count, TagDirection, Timeline, DEFAULT_DURATION},
+};
+
+use super::{png::save_png, DocumentPath, FileError};
+
+/// how the frames are placed on the sprite sheet.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SheetLayout {
+    /// rows and columns of equally sized cells
+    #[default]
+    Grid,
+    /// every frame in a single row
+    Horizontal,
+    /// frames of different sizes (when trimmed) packed in rows, tallest first
+    Packed,
+}
+
+impl SheetLayout {
+    pub fn next(self) -> Self {
+        match self {
+            SheetLayout::Grid => SheetLayout::Horizontal,
+            SheetLayout::Horizontal => SheetLayout::Packed,
+            SheetLayout::Packed => SheetLayout::Grid,
+        }
+    }
+
+    pub fn parse(layout: &str) -> Option<Self> {
+        match layout {
+            "grid" => Some(SheetLayout::Grid),
+            "horizontal" | "strip" => Some(SheetLayout::Horizontal),
+            "packed" => Some(SheetLayout::Packed),
+            _ => None,
+        }
+    }
+}
+
+/// the two layouts of aseprite's json, the frames are either a list or a map by filename.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonFormat {
+    Array,
+    #[default]
+    Hash,
+}
+
+impl JsonFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "array" => Some(JsonFormat::Array),
+            "hash" => Some(JsonFormat::Hash),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct SheetSettings {
+    pub layout: SheetLayout,
+    /// space between the frames and around the edge of the sheet
+    pub padding: u32,
+    /// repeat the edge pixels of every frame this many times, stops texture bleeding
+    pub extrude: u32,
+    /// cut the transparent border off every frame
+    pub trim: bool,
+    pub json: JsonFormat,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SheetRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct SheetSize {
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SheetFrame {
+    /// where the frame is on the sheet, without the extruded pixels
+    pub frame: SheetRect,
+    pub rotated: bool,
+    pub trimmed: bool,
+    /// the part of the canvas that's on the sheet
+    pub sprite_source_size: SheetRect,
+    pub source_size: SheetSize,
+    /// in milliseconds
+    pub duration: u32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FrameTag {
+    pub name: String,
+    pub from: u32,
+    pub to: u32,
+    /// `forward`, `reverse` or `pingpong`
+    pub direction: &'static str,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SheetMeta {
+    pub app: &'static str,
+    pub version: &'static str,
+    /// the file name of the sprite sheet
+    pub image: String,
+    pub format: &'static str,
+    pub size: SheetSize,
+    pub scale: &'static str,
+    pub frame_tags: Vec<FrameTag>,
+}
+
+/// the sidecar json of a sprite sheet, in the same layout as aseprite's.
+#[derive(Serialize, Clone, Debug)]
+pub struct SheetJson {
+    pub frames: SheetFrames,
+    pub meta: SheetMeta,
+}
+
+/// the frames and their file names, in order.
+#[derive(Clone, Debug)]
+pub struct SheetFrames {
+    pub format: JsonFormat,
+    pub frames: Vec<(String, SheetFrame)>,
+}
+
+impl Serialize for SheetFrames {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Named<'a> {
+            filename: &'a str,
+            #[serde(flatten)]
+            frame: &'a SheetFrame,
+        }
+
+        match self.format {
+            JsonFormat::Array => serializer.collect_seq(
+                self.frames
+                    .iter()
+                    .map(|(filename, frame)| Named { filename, frame }),
+            ),
+            // a map keeps the order of the frames, unlike a `BTreeMap`
+            JsonFormat::Hash => {
+                let mut map = serializer.serialize_map(Some(self.frames.len()))?;
+                for (filename, frame) in &self.frames {
+                    map.serialize_entry(filename, frame)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+pub fn direction_name(direction: TagDirection) -> &'static str {
+    match direction {
+        TagDirection::Forward => "forward",
+        TagDirection::Reverse => "reverse",
+        TagDirection::PingPong => "pingpong",
+    }
+}
+
+/// the smallest rect containing every visible pixel, `None` if there are none.
+pub fn visible_rect(data: &[u8], width: u32, height: u32) -> Option<SheetRect> {
+    let (mut min, mut max) = (UVec2::MAX, UVec2::ZERO);
+    for (i, pixel) in data.array_chunks::<4>().enumerate() {
+        if pixel[3] != 0 {
+            let pos = UVec2::new(i as u32 % width, i as u32 / width);
+            min = min.min(pos);
+            max = max.max(pos);
+        }
+    }
+    (min.x < width && min.y < height).then(|| SheetRect {
+        x: min.x,
+        y: min.y,
+        w: max.x - min.x + 1,
+        h: max.y - min.y + 1,
+    })
+}
+
+/// the top left corner of every rect and the size of the sheet.
+///
+/// `sizes` already include the extruded pixels, `padding` goes between and around them.
+pub fn layout_rects(sizes: &[UVec2], layout: SheetLayout, padding: u32) -> (Vec<UVec2>, UVec2) {
+    let mut positions = vec![UVec2::ZERO; sizes.len()];
+    if sizes.is_empty() {
+        return (positions, UVec2::ONE);
+    }
+
+    match layout {
+        SheetLayout::Grid => {
+            let cell = sizes.iter().fold(UVec2::ZERO, |a, b| a.max(*b)) + padding;
+            let columns = (sizes.len() as f32).sqrt().ceil() as u32;
+            for (i, pos) in positions.iter_mut().enumerate() {
+                let i = i as u32;
+                *pos = UVec2::new(i % columns, i / columns) * cell + padding;
+            }
+        }
+        SheetLayout::Horizontal => {
+            let mut x = padding;
+            for (pos, size) in positions.iter_mut().zip(sizes) {
+                *pos = UVec2::new(x, padding);
+                x += size.x + padding;
+            }
+        }
+        SheetLayout::Packed => {
+            // rows about as wide as the sheet is tall
+            let area = sizes
+                .iter()
+                .map(|size| ((size.x + padding) * (size.y + padding)) as f32)
+                .sum::<f32>();
+            let widest = sizes.iter().map(|size| size.x).max().unwrap();
+            let max_width = (area.sqrt().ceil() as u32).max(widest) + padding;
+
+            let mut order = (0..sizes.len()).collect::<Vec<_>>();
+            order.sort_by_key(|i| std::cmp::Reverse(sizes[*i].y));
+
+            let (mut x, mut y, mut row_height) = (padding, padding, 0);
+            for i in order {
+                let size = sizes[i];
+                if x > padding && x + size.x + padding > max_width {
+                    x = padding;
+                    y += row_height + padding;
+                    row_height = 0;
+                }
+                positions[i] = UVec2::new(x, y);
+                x += size.x + padding;
+                row_height = row_height.max(size.y);
+            }
+        }
+    }
+
+    let size = positions
+        .iter()
+        .zip(sizes)
+        .fold(UVec2::ONE, |a, (pos, size)| a.max(*pos + *size + padding));
+    (positions, size)
+}
+
+/// copy `rect` of `data` to `pos` on the sheet, with its edges repeated `extrude` times.
+fn blit(
+    sheet: &mut [u8],
+    sheet_width: u32,
+    data: &[u8],
+    width: u32,
+    rect: SheetRect,
+    pos: UVec2,
+    extrude: u32,
+) {
+    let extrude = extrude as i32;
+    for y in -extrude..rect.h as i32 + extrude {
+        for x in -extrude..rect.w as i32 + extrude {
+            let src_x = rect.x + x.clamp(0, rect.w as i32 - 1) as u32;
+            let src_y = rect.y + y.clamp(0, rect.h as i32 - 1) as u32;
+            let src = ((src_y * width + src_x) * 4) as usize;
+            let dst_x = (pos.x as i32 + x + extrude) as u32;
+            let dst_y = (pos.y as i32 + y + extrude) as u32;
+            let dst = ((dst_y * sheet_width + dst_x) * 4) as usize;
+            sheet[dst..dst + 4].copy_from_slice(&data[src..src + 4]);
+        }
+    }
+}
+
+/// place `frames` (rgba8 data and duration in milliseconds) on a sprite sheet.
+///
+/// returns the sheet data, its size and the json describing it, `image` is the file name
+/// of the sheet written in the json.
+pub fn build_sheet(
+    width: u32,
+    height: u32,
+    frames: &[(Vec<u8>, u32)],
+    tags: Vec<FrameTag>,
+    name: &str,
+    image: String,
+    settings: &SheetSettings,
+) -> (Vec<u8>, UVec2, SheetJson) {
+    let full = SheetRect {
+        x: 0,
+        y: 0,
+        w: width,
+        h: height,
+    };
+    let rects = frames
+        .iter()
+        .map(|(data, _)| match settings.trim {
+            // an empty frame keeps a single transparent pixel
+            true => visible_rect(data, width, height).unwrap_or(SheetRect { w: 1, h: 1, ..full }),
+            false => full,
+        })
+        .collect::<Vec<_>>();
+
+    let sizes = rects
+        .iter()
+        .map(|rect| UVec2::new(rect.w, rect.h) + settings.extrude * 2)
+        .collect::<Vec<_>>();
+    let (positions, size) = layout_rects(&sizes, settings.layout, settings.padding);
+
+    let mut sheet = vec![0; (size.x * size.y * 4) as usize];
+    let mut json_frames = Vec::new();
+    for (i, (((data, duration), rect), pos)) in
+        frames.iter().zip(&rects).zip(&positions).enumerate()
+    {
+        blit(
+            &mut sheet,
+            size.x,
+            data,
+            width,
+            *rect,
+            *pos,
+            settings.extrude,
+        );
+        json_frames.push((
+            format!("{name} {i}"),
+            SheetFrame {
+                frame: SheetRect {
+                    x: pos.x + settings.extrude,
+                    y: pos.y + settings.extrude,
+                    w: rect.w,
+                    h: rect.h,
+                },
+                rotated: false,
+                trimmed: *rect != full,
+                sprite_source_size: *rect,
+                source_size: SheetSize {
+                    w: width,
+                    h: height,
+                },
+                duration: *duration,
+            },
+        ));
+    }
+
+    let json = SheetJson {
+        frames: SheetFrames {
+            format: settings.json,
+            frames: json_frames,
+        },
+        meta: SheetMeta {
+            app: "sprinkle",
+            version: env!("CARGO_PKG_VERSION"),
+            image,
+            format: "RGBA8888",
+            size: SheetSize {
+                w: size.x,
+                h: size.y,
+            },
+            scale: "1",
+            frame_tags: tags,
+        },
+    };
+    (sheet, size, json)
+}
+
+pub fn save_sheet(
+    path: impl AsRef<Path>,
+    json_path: impl AsRef<Path>,
+    sheet: &[u8],
+    size: UVec2,
+    json: &SheetJson,
+) -> Result<(), FileError> {
+    save_png(path, size.x, size.y, sheet)?;
+    let writer = BufWriter::new(File::create(json_path)?);
+    serde_json::to_writer_pretty(writer, json)?;
+    Ok(())
+}
+
+/// [ctrl + p] : export every frame as a sprite sheet with an aseprite compatible json
+/// [ctrl + shift + p] : cycle through the grid, horizontal and packed sprite sheet layouts
+pub fn export_sheet(
+    input: Res<Input<KeyCode>>,
+    canvas: Res<Canvas>,
+    tree: LayerTree,
+    layers: Query<&Layer, Without<Detached>>,
+    images: Res<Assets<Image>>,
+    mut settings: ResMut<SheetSettings>,
+    path: Res<DocumentPath>,
+) {
+    if !(input.pressed(KeyCode::LControl)
+        && !input.pressed(KeyCode::LAlt)
+        && input.just_pressed(KeyCode::P))
+    {
+        return;
+    }
+
+    if input.pressed(KeyCode::LShift) {
+        settings.layout = settings.layout.next();
+        info!("sprite sheet layout: {:?}", settings.layout);
+        return;
+    }
+
+    // durations and tags come from the timeline of the active layer
+    let timeline = tree
+        .layers
+        .get(canvas.layer_id)
+        .ok()
+        .and_then(|(layer, _)| tree.timelines.get(layer.timeline_id?).ok());
+
+    let count = total_frame_count(&layers, &tree.timelines);
+    let frames = (0..count)
+        .map(|frame| {
+            let duration = timeline.map_or(DEFAULT_DURATION, |timeline| {
+                timeline.duration(timeline.local_frame(frame))
+            });
+            (
+                tree.flatten(canvas.width, canvas.height, Some(frame), &images),
+                duration,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let tags = timeline
+        .map(|timeline: &Timeline| {
+            let last = count - 1;
+            let offset = timeline.frame_offset();
+            timeline
+                .tags()
+                .iter()
+                .filter(|tag| tag.from + offset <= last && tag.to + offset >= 0)
+                .map(|tag| FrameTag {
+                    name: tag.name.clone(),
+                    from: (tag.from + offset).clamp(0, last) as u32,
+                    to: (tag.to + offset).clamp(0, last) as u32,
+                    direction: direction_name(tag.direction),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let stem = path.0.file_stem().unwrap_or_default().to_string_lossy();
+    let sheet_path = path.0.with_file_name(format!("{stem}-sheet.png"));
+    let json_path = path.0.with_file_name(format!("{stem}-sheet.json"));
+    let (sheet, size, json) = build_sheet(
+        canvas.width,
+        canvas.height,
+        &frames,
+        tags,
+        &stem,
+        format!("{stem}-sheet.png"),
+        &settings,
+    );
+
+    match save_sheet(&sheet_path, &json_path, &sheet, size, &json) {
+        Ok(()) => info!(
+            "exported {} frames to {:?} ({}x{})",
+            frames.len(),
+            sheet_path,
+            size.x,
+            size.y
+        ),
+        Err(err) => error!("failed to export {:?}: {}", sheet_path, err),
+    }
+}
@@ -0,0 +1,478 @@
+use bevy::prelude::*;
+
+use crate::{
+    canvas::Canvas,
+    composite::BlendMode,
+    layer::{Layer, LayerBundle},
+    timeline::Timeline,
+    History, HistoryItem, NodeProperties,
+};
+
+/// a folder of layers and other groups, the nodes in it are its [`Children`] (bottom first).
+#[derive(Component)]
+pub struct LayerGroup {
+    pub name: String,
+    pub visible: bool,
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+    /// the layers of a collapsed group are skipped when stepping through layers
+    pub collapsed: bool,
+    pub timeline_id: Option<Entity>,
+}
+
+impl LayerGroup {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for LayerGroup {
+    fn default() -> Self {
+        LayerGroup {
+            name: String::from("Group"),
+            visible: true,
+            opacity: 1.0,
+            blend_mode: BlendMode::Normal,
+            collapsed: false,
+            timeline_id: None,
+        }
+    }
+}
+
+#[derive(Bundle, Default)]
+pub struct GroupBundle {
+    pub group: LayerGroup,
+    pub spatial: SpatialBundle,
+}
+
+/// a layer or group that was taken out of the document, it's kept around so
+/// [`History`](crate::History) can put it back.
+#[derive(Component)]
+pub struct Detached;
+
+/// where a layer or group is stored, either in a group or at the top of a timeline.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Container {
+    Group(Entity),
+    Timeline(Entity),
+}
+
+impl Container {
+    pub fn of(node: Entity, timeline_id: Entity, parents: &Query<&Parent>) -> Self {
+        match parents.get(node) {
+            Ok(parent) => Container::Group(parent.get()),
+            Err(_) => Container::Timeline(timeline_id),
+        }
+    }
+
+    pub fn nodes(
+        &self,
+        groups: &Query<(&LayerGroup, Option<&Children>)>,
+        timelines: &Query<&mut Timeline>,
+    ) -> Vec<Entity> {
+        match *self {
+            Container::Group(id) => children_of(id, groups).to_vec(),
+            Container::Timeline(id) => timelines
+                .get(id)
+                .map(|timeline| timeline.layers().to_vec())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// move `node` to `index`, the node is removed from its old container first.
+    pub fn insert(
+        &self,
+        index: usize,
+        node: Entity,
+        commands: &mut Commands,
+        timelines: &mut Query<&mut Timeline>,
+    ) {
+        match *self {
+            Container::Group(id) => {
+                commands.entity(id).insert_children(index, &[node]);
+            }
+            Container::Timeline(id) => {
+                commands.entity(node).remove_parent();
+                let mut timeline = timelines.get_mut(id).unwrap();
+                if let Some(i) = timeline.index_of(node) {
+                    timeline.remove(i);
+                }
+                timeline.insert(index, node);
+            }
+        }
+    }
+
+    /// take `node` out of this container without despawning it.
+    pub fn remove(
+        &self,
+        node: Entity,
+        commands: &mut Commands,
+        timelines: &mut Query<&mut Timeline>,
+    ) {
+        match *self {
+            Container::Group(id) => {
+                commands.entity(id).remove_children(&[node]);
+            }
+            Container::Timeline(id) => {
+                let mut timeline = timelines.get_mut(id).unwrap();
+                if let Some(i) = timeline.index_of(node) {
+                    timeline.remove(i);
+                }
+            }
+        }
+    }
+
+    /// replace every node of this container with `nodes`, they're taken out of their old
+    /// containers first.
+    pub fn set_nodes(
+        &self,
+        nodes: &[Entity],
+        commands: &mut Commands,
+        timelines: &mut Query<&mut Timeline>,
+    ) {
+        match *self {
+            Container::Group(id) => {
+                commands.entity(id).clear_children().push_children(nodes);
+            }
+            Container::Timeline(id) => {
+                for node in nodes {
+                    commands.entity(*node).remove_parent();
+                }
+                timelines.get_mut(id).unwrap().set_layers(nodes.to_vec());
+            }
+        }
+        // everything in a container is part of the document
+        for node in nodes {
+            commands.entity(*node).remove::<Detached>();
+        }
+    }
+
+    /// put a detached node back at `index`.
+    pub fn attach(
+        &self,
+        index: usize,
+        node: Entity,
+        commands: &mut Commands,
+        timelines: &mut Query<&mut Timeline>,
+        groups: &Query<(&LayerGroup, Option<&Children>)>,
+    ) {
+        self.insert(index, node, commands, timelines);
+        set_detached(node, false, commands, groups);
+    }
+
+    /// take `node` out of the document without despawning it, see [`Detached`].
+    pub fn detach(
+        &self,
+        node: Entity,
+        commands: &mut Commands,
+        timelines: &mut Query<&mut Timeline>,
+        groups: &Query<(&LayerGroup, Option<&Children>)>,
+    ) {
+        self.remove(node, commands, timelines);
+        set_detached(node, true, commands, groups);
+    }
+}
+
+/// mark `node` and everything in it as detached, or not.
+pub fn set_detached(
+    node: Entity,
+    detached: bool,
+    commands: &mut Commands,
+    groups: &Query<(&LayerGroup, Option<&Children>)>,
+) {
+    for child in children_of(node, groups) {
+        set_detached(*child, detached, commands, groups);
+    }
+    if detached {
+        commands.entity(node).insert(Detached);
+    } else {
+        commands.entity(node).remove::<Detached>();
+    }
+}
+
+pub fn children_of<'a>(
+    group: Entity,
+    groups: &'a Query<(&LayerGroup, Option<&Children>)>,
+) -> &'a [Entity] {
+    match groups.get(group) {
+        Ok((_, Some(children))) => &**children,
+        _ => &[],
+    }
+}
+
+/// every layer in `nodes` and their groups in drawing order (bottom first).
+///
+/// the layers of collapsed groups are skipped if `skip_collapsed` is set.
+pub fn ordered_layers(
+    nodes: &[Entity],
+    groups: &Query<(&LayerGroup, Option<&Children>)>,
+    skip_collapsed: bool,
+) -> Vec<Entity> {
+    let mut out = Vec::new();
+    for node in nodes {
+        match groups.get(*node) {
+            Ok((group, children)) => {
+                if !(skip_collapsed && group.collapsed) {
+                    if let Some(children) = children {
+                        out.extend(ordered_layers(children, groups, skip_collapsed));
+                    }
+                }
+            }
+            Err(_) => out.push(*node),
+        }
+    }
+    out
+}
+
+/// copy a group and everything in it, returns the new group.
+pub fn duplicate_group(
+    group_id: Entity,
+    commands: &mut Commands,
+    layers: &Query<&Layer>,
+    groups: &Query<(&LayerGroup, Option<&Children>)>,
+    images: &mut Assets<Image>,
+) -> Entity {
+    let (group, children) = groups.get(group_id).unwrap();
+
+    let children = children
+        .map(|children| children.to_vec())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|child| match layers.get(child) {
+            Ok(layer) => commands
+                .spawn(LayerBundle::new(layer.duplicate(images)))
+                .id(),
+            Err(_) => duplicate_group(child, commands, layers, groups, images),
+        })
+        .collect::<Vec<_>>();
+
+    commands
+        .spawn(GroupBundle {
+            group: LayerGroup {
+                name: format!("{} copy", group.name),
+                visible: group.visible,
+                opacity: group.opacity,
+                blend_mode: group.blend_mode,
+                collapsed: group.collapsed,
+                timeline_id: group.timeline_id,
+            },
+            ..Default::default()
+        })
+        .push_children(&children)
+        .id()
+}
+
+/// [ctrl + g] : put the active layer in a new group
+/// [ctrl + shift + g] : ungroup the group of the active layer
+/// [tab] : collapse / expand the group of the active layer
+/// [ctrl + h] : show / hide the group
+/// [ctrl + - / =] : decrease / increase the opacity of the group
+/// [ctrl + m] : cycle through the blend modes of the group
+/// [ctrl + shift + j] : duplicate the group
+/// [ctrl + delete] : delete the group and everything in it
+/// [ctrl + shift + page up / page down] : move the group up / down
+pub fn group_input(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut canvas: ResMut<Canvas>,
+    layers: Query<&Layer>,
+    mut groups: Query<(&mut LayerGroup, Option<&Children>)>,
+    parents: Query<&Parent>,
+    mut timelines: Query<&mut Timeline>,
+    mut images: ResMut<Assets<Image>>,
+    mut history: ResMut<History>,
+) {
+    let Some(timeline_id) = layers
+        .get(canvas.layer_id)
+        .ok()
+        .and_then(|layer| layer.timeline_id)
+    else {
+        return;
+    };
+
+    let ctrl = input.pressed(KeyCode::LControl);
+    let shift = input.pressed(KeyCode::LShift);
+
+    if ctrl && !shift && input.just_pressed(KeyCode::G) {
+        let container = Container::of(canvas.layer_id, timeline_id, &parents);
+        let nodes = container.nodes(&groups.to_readonly(), &timelines);
+        let index = nodes
+            .iter()
+            .position(|id| *id == canvas.layer_id)
+            .unwrap_or(0);
+
+        container.remove(canvas.layer_id, &mut commands, &mut timelines);
+        let group = commands
+            .spawn(GroupBundle {
+                group: LayerGroup {
+                    timeline_id: Some(timeline_id),
+                    ..LayerGroup::new(format!("Group {}", groups.iter().count() + 1))
+                },
+                ..Default::default()
+            })
+            .push_children(&[canvas.layer_id])
+            .id();
+        container.insert(index, group, &mut commands, &mut timelines);
+        // undoing takes the group out of the document before the layer is moved back
+        let name = &layers.get(canvas.layer_id).unwrap().name;
+        history.add(
+            format!("Group {}", name),
+            HistoryItem::Compound(
+                String::from("group layer"),
+                vec![
+                    HistoryItem::Nodes {
+                        nodes: vec![(group, container, index)],
+                        attached: true,
+                    },
+                    HistoryItem::Order(container, nodes),
+                    HistoryItem::Order(Container::Group(group), Vec::new()),
+                ],
+            ),
+        );
+        info!("grouped layer");
+        return;
+    }
+
+    // everything below needs the group of the active layer
+    let Ok(group_id) = parents.get(canvas.layer_id).map(|parent| parent.get()) else {
+        return;
+    };
+    let container = Container::of(group_id, timeline_id, &parents);
+    let nodes = container.nodes(&groups.to_readonly(), &timelines);
+    let index = nodes.iter().position(|id| *id == group_id).unwrap_or(0);
+    let name = groups.get(group_id).unwrap().0.name.clone();
+
+    if ctrl && shift && input.just_pressed(KeyCode::G) {
+        let children = children_of(group_id, &groups.to_readonly()).to_vec();
+        for (i, child) in children.iter().enumerate() {
+            container.insert(index + i, *child, &mut commands, &mut timelines);
+        }
+        // only the empty group is detached, its layers stay in the document
+        container.remove(group_id, &mut commands, &mut timelines);
+        commands.entity(group_id).insert(Detached);
+        // redoing takes the group out of the document before its layers are moved out
+        history.add(
+            format!("Ungroup {}", name),
+            HistoryItem::Compound(
+                String::from("ungroup"),
+                vec![
+                    HistoryItem::Order(Container::Group(group_id), children),
+                    HistoryItem::Order(container, nodes),
+                    HistoryItem::Nodes {
+                        nodes: vec![(group_id, container, index)],
+                        attached: false,
+                    },
+                ],
+            ),
+        );
+        info!("ungrouped layers");
+        return;
+    }
+
+    if ctrl && shift && input.just_pressed(KeyCode::J) {
+        let copy = duplicate_group(
+            group_id,
+            &mut commands,
+            &layers,
+            &groups.to_readonly(),
+            &mut images,
+        );
+        container.insert(index + 1, copy, &mut commands, &mut timelines);
+        history.add(
+            format!("Duplicate {}", name),
+            HistoryItem::Nodes {
+                nodes: vec![(copy, container, index + 1)],
+                attached: true,
+            },
+        );
+        info!("duplicated group");
+        return;
+    }
+
+    if ctrl && input.just_pressed(KeyCode::Delete) {
+        // pick a layer outside of the group to be the new active layer
+        let removed = ordered_layers(&[group_id], &groups.to_readonly(), false);
+        let all = timelines
+            .get(timeline_id)
+            .map(|timeline| ordered_layers(timeline.layers(), &groups.to_readonly(), false))
+            .unwrap_or_default();
+        let Some(next) = all.into_iter().filter(|id| !removed.contains(id)).last() else {
+            warn!("can't delete the only group");
+            return;
+        };
+
+        container.detach(
+            group_id,
+            &mut commands,
+            &mut timelines,
+            &groups.to_readonly(),
+        );
+        history.add(
+            format!("Delete {}", name),
+            HistoryItem::Compound(
+                String::from("delete group"),
+                vec![
+                    HistoryItem::Nodes {
+                        nodes: vec![(group_id, container, index)],
+                        attached: false,
+                    },
+                    HistoryItem::ActiveLayer(canvas.layer_id),
+                ],
+            ),
+        );
+        canvas.layer_id = next;
+        info!("deleted group");
+        return;
+    }
+
+    if ctrl && shift && input.just_pressed(KeyCode::PageUp) && index + 1 < nodes.len() {
+        container.insert(index + 1, group_id, &mut commands, &mut timelines);
+        history.add(
+            format!("Move {} up", name),
+            HistoryItem::Order(container, nodes.clone()),
+        );
+    }
+    if ctrl && shift && input.just_pressed(KeyCode::PageDown) && index > 0 {
+        container.insert(index - 1, group_id, &mut commands, &mut timelines);
+        history.add(
+            format!("Move {} down", name),
+            HistoryItem::Order(container, nodes.clone()),
+        );
+    }
+
+    let (mut group, _) = groups.get_mut(group_id).unwrap();
+    if input.just_pressed(KeyCode::Tab) {
+        group.collapsed = !group.collapsed;
+        info!("{} collapsed: {}", group.name, group.collapsed);
+    }
+    if !ctrl {
+        return;
+    }
+    let before = NodeProperties::of_group(&group);
+    if input.just_pressed(KeyCode::H) {
+        group.visible = !group.visible;
+        info!("{} visible: {}", group.name, group.visible);
+    }
+    if input.just_pressed(KeyCode::Minus) {
+        group.opacity = (group.opacity - 0.1).max(0.0);
+        info!("{} opacity: {:.1}", group.name, group.opacity);
+    }
+    if input.just_pressed(KeyCode::Equals) {
+        group.opacity = (group.opacity + 0.1).min(1.0);
+        info!("{} opacity: {:.1}", group.name, group.opacity);
+    }
+    if input.just_pressed(KeyCode::M) {
+        group.blend_mode = group.blend_mode.next();
+        info!("{} blend mode: {:?}", group.name, group.blend_mode);
+    }
+    let after = NodeProperties::of_group(&group);
+    if after != before {
+        history.add(
+            before.label(&after, &name),
+            HistoryItem::Properties(group_id, before),
+        );
+    }
+}
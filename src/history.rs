@@ -0,0 +1,163 @@
+use std::time::SystemTime;
+
+use bevy::prelude::*;
+
+use crate::{tools::MoveState, Document, History};
+
+/// how many entries the history panel shows around the picked one.
+const SHOWN_ENTRIES: usize = 20;
+
+/// the list of [`History`] entries in the corner of the window.
+#[derive(Resource, Default)]
+pub struct HistoryPanel {
+    pub visible: bool,
+    /// the picked state, 0 is the state before the oldest entry
+    pub cursor: usize,
+    /// the state the document is going to, see [`jump_history`]
+    target: Option<usize>,
+}
+
+#[derive(Component)]
+pub struct HistoryText;
+
+pub fn setup_history_panel(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::default().with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.0),
+            left: Val::Px(8.0),
+            ..Default::default()
+        }),
+        HistoryText,
+    ));
+}
+
+/// [ctrl + u] : show / hide the history panel
+/// [ctrl + up / down] : pick an older / newer state in the history panel
+/// [ctrl + enter] : go to the picked state, undoing or redoing every step in between
+pub fn history_panel_input(
+    input: Res<Input<KeyCode>>,
+    mut panel: ResMut<HistoryPanel>,
+    history: Res<History>,
+) {
+    if !input.pressed(KeyCode::LControl) {
+        return;
+    }
+    if input.just_pressed(KeyCode::U) {
+        panel.visible = !panel.visible;
+        panel.cursor = history.position();
+    }
+    if !panel.visible {
+        return;
+    }
+
+    if input.just_pressed(KeyCode::Up) {
+        panel.cursor = panel.cursor.saturating_sub(1);
+    }
+    if input.just_pressed(KeyCode::Down) {
+        panel.cursor = (panel.cursor + 1).min(history.len());
+    }
+    if input.just_pressed(KeyCode::Return) {
+        info!("going to state {} of {}", panel.cursor, history.len());
+        panel.target = Some(panel.cursor);
+    }
+}
+
+/// undo or redo one entry per frame until the picked state is reached, so the commands of
+/// an entry are applied before the next one looks at the document.
+pub fn jump_history(
+    mut panel: ResMut<HistoryPanel>,
+    mut history: ResMut<History>,
+    move_state: Res<MoveState>,
+    mut doc: Document,
+) {
+    let Some(target) = panel.target else {
+        return;
+    };
+    if move_state.is_floating() {
+        warn!("put the moved pixels down before jumping through the history");
+        panel.target = None;
+        return;
+    }
+    let target = target.min(history.len());
+
+    if history.position() > target {
+        if let Some(entry) = history.undo(&mut doc) {
+            info!("undo {}", entry.label);
+        }
+    } else if history.position() < target {
+        if let Some(entry) = history.redo(&mut doc) {
+            info!("redo {}", entry.label);
+        }
+    }
+    if history.position() == target {
+        panel.target = None;
+    }
+}
+
+/// like "12s ago" or "3h ago".
+fn time_ago(now: SystemTime, time: SystemTime) -> String {
+    let secs = now.duration_since(time).unwrap_or_default().as_secs();
+    if secs < 60 {
+        format!("{secs}s ago")
+    } else if secs < 60 * 60 {
+        format!("{}m ago", secs / 60)
+    } else if secs < 24 * 60 * 60 {
+        format!("{}h ago", secs / (60 * 60))
+    } else {
+        format!("{}d ago", secs / (24 * 60 * 60))
+    }
+}
+
+/// list the entries around the picked one, undone entries are greyed out.
+pub fn update_history_panel(
+    panel: Res<HistoryPanel>,
+    history: Res<History>,
+    mut text: Query<(&mut Text, &mut Visibility), With<HistoryText>>,
+) {
+    // the times keep changing while the panel is shown
+    if !panel.visible && !panel.is_changed() {
+        return;
+    }
+    let Ok((mut text, mut visibility)) = text.get_single_mut() else {
+        return;
+    };
+    *visibility = if panel.visible {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+    if !panel.visible {
+        return;
+    }
+
+    let now = SystemTime::now();
+    let entries = std::iter::once((String::from("Original"), None)).chain(
+        history
+            .entries()
+            .map(|entry| (entry.label.clone(), Some(entry.time))),
+    );
+    let first = panel.cursor.saturating_sub(SHOWN_ENTRIES / 2);
+    text.sections = entries
+        .enumerate()
+        .skip(first)
+        .take(SHOWN_ENTRIES)
+        .map(|(i, (label, time))| {
+            let picked = if i == panel.cursor { "> " } else { "  " };
+            let ago = time.map_or_else(String::new, |time| format!(" ({})", time_ago(now, time)));
+            let color = if i > history.position() {
+                Color::GRAY
+            } else {
+                Color::WHITE
+            };
+            TextSection::new(
+                format!("{picked}{label}{ago}\n"),
+                TextStyle {
+                    font_size: 16.0,
+                    color,
+                    ..Default::default()
+                },
+            )
+        })
+        .collect();
+}
@@ -1,30 +1,332 @@
-use bevy::{prelude::*, utils::HashMap};
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    canvas::Canvas,
+    composite::{blend, BlendMode, LayerTree},
+    group::{ordered_layers, Container, LayerGroup},
+    timeline::{GlobalTimeline, Timeline},
+    tools::MoveState,
+    History, HistoryItem, NodeProperties,
+};
+
+/// a frame taken out of a layer.
+#[derive(Clone)]
+pub enum Cel {
+    /// the pixels of an image no other frame uses
+    Image(Vec<u8>),
+    /// linked to the image of another frame, numbered as if the cel was still there
+    Linked(i32),
+}
 
 #[derive(Component)]
 pub struct Layer {
+    pub name: String,
     pub frames: HashMap<i32, Handle<Image>>,
     pub timeline_id: Option<Entity>,
+    pub visible: bool,
+    /// locked layers can't be drawn on
+    pub locked: bool,
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+    /// show the frames around the current one in the onion skin
+    pub onion_skin: bool,
 }
 
 impl Layer {
-    pub fn new(image: Handle<Image>, timeline_id: Option<Entity>) -> Self {
+    pub fn new(name: impl Into<String>, image: Handle<Image>, timeline_id: Option<Entity>) -> Self {
         Self {
+            name: name.into(),
             frames: HashMap::from([(0, image)]),
             timeline_id,
+            ..Default::default()
         }
     }
+
+    /// tools refuse to draw on hidden or locked layers.
+    pub fn is_editable(&self) -> bool {
+        self.visible && !self.locked
+    }
+
+    /// one more than the last frame, a layer always has at least one frame.
+    pub fn frame_count(&self) -> i32 {
+        self.frames.keys().max().map_or(1, |last| last + 1)
+    }
+
+    /// move every frame from `frame` onwards one to the right and put `image` at `frame`,
+    /// `None` leaves an empty cel.
+    pub fn insert_frame(&mut self, frame: i32, image: Option<Handle<Image>>) {
+        self.frames = std::mem::take(&mut self.frames)
+            .into_iter()
+            .map(|(i, handle)| (if i >= frame { i + 1 } else { i }, handle))
+            .collect();
+        if let Some(image) = image {
+            self.frames.insert(frame, image);
+        }
+    }
+
+    /// remove `frame` and move every frame after it one to the left.
+    pub fn remove_frame(&mut self, frame: i32) -> Option<Handle<Image>> {
+        let image = self.frames.remove(&frame);
+        self.frames = std::mem::take(&mut self.frames)
+            .into_iter()
+            .map(|(i, handle)| (if i > frame { i - 1 } else { i }, handle))
+            .collect();
+        image
+    }
+
+    /// remove `frame` like [`remove_frame`](Self::remove_frame), a linked frame only remembers
+    /// which frame it's linked to.
+    pub fn take_cel(&mut self, frame: i32, images: &Assets<Image>) -> Option<Cel> {
+        let cel = match self.linked_frames(frame).first() {
+            Some(other) => Some(Cel::Linked(*other)),
+            None => self
+                .frames
+                .get(&frame)
+                .and_then(|handle| images.get(handle))
+                .map(|image| Cel::Image(image.data.clone())),
+        };
+        self.remove_frame(frame);
+        cel
+    }
+
+    /// put a cel taken by [`take_cel`](Self::take_cel) back at `frame`.
+    pub fn restore_cel(
+        &mut self,
+        frame: i32,
+        cel: Option<Cel>,
+        images: &mut Assets<Image>,
+        width: u32,
+        height: u32,
+    ) {
+        self.insert_frame(frame, None);
+        let handle = match cel {
+            Some(Cel::Image(data)) => {
+                let mut image = crate::image(width, height, Color::NONE);
+                image.data = data;
+                images.add(image)
+            }
+            Some(Cel::Linked(other)) => match self.frames.get(&other) {
+                Some(handle) => handle.clone(),
+                None => return,
+            },
+            None => return,
+        };
+        self.frames.insert(frame, handle);
+    }
+
+    /// the other frames sharing the image of `frame`.
+    pub fn linked_frames(&self, frame: i32) -> Vec<i32> {
+        let Some(handle) = self.frames.get(&frame) else {
+            return Vec::new();
+        };
+        let mut frames = self
+            .frames
+            .iter()
+            .filter(|(other, h)| **other != frame && *h == handle)
+            .map(|(other, _)| *other)
+            .collect::<Vec<_>>();
+        frames.sort();
+        frames
+    }
+
+    pub fn swap_frames(&mut self, a: i32, b: i32) {
+        let image_a = self.frames.remove(&a);
+        let image_b = self.frames.remove(&b);
+        if let Some(image) = image_a {
+            self.frames.insert(b, image);
+        }
+        if let Some(image) = image_b {
+            self.frames.insert(a, image);
+        }
+    }
+
+    /// copy every frame into new images.
+    pub fn duplicate(&self, images: &mut Assets<Image>) -> Self {
+        let frames = self
+            .frames
+            .iter()
+            .filter_map(|(frame, handle)| {
+                let image = images.get(handle)?.clone();
+                Some((*frame, images.add(image)))
+            })
+            .collect();
+
+        Self {
+            name: format!("{} copy", self.name),
+            frames,
+            timeline_id: self.timeline_id,
+            visible: self.visible,
+            locked: self.locked,
+            opacity: self.opacity,
+            blend_mode: self.blend_mode,
+            onion_skin: self.onion_skin,
+        }
+    }
+}
+
+/// blend every frame of `layer` on top of the same frame of `target`, using the opacity and
+/// blend mode of `layer`. frames missing from `target` are added.
+///
+/// returns the frames of `target` before merging, and the frames of `target` that were
+/// unlinked with the frame they were linked to.
+pub fn merge_layer(
+    layer: &Layer,
+    target: &mut Layer,
+    images: &mut Assets<Image>,
+    width: u32,
+    height: u32,
+) -> (Vec<(i32, Option<Vec<u8>>)>, Vec<(i32, i32)>) {
+    // a frame of `target` linked to a frame that gets something else blended on it gets its
+    // own copy of the image first
+    let mut target_frames = target.frames.keys().copied().collect::<Vec<_>>();
+    target_frames.sort();
+    let mut sources = HashMap::new();
+    let mut unlinked = Vec::new();
+    for frame in target_frames {
+        let handle = target.frames[&frame].clone();
+        let source = layer.frames.get(&frame);
+        match sources.get(&handle.id()) {
+            None => {
+                sources.insert(handle.id(), (frame, source));
+            }
+            Some((first, first_source)) if *first_source != source => {
+                let Some(copy) = images.get(&handle).cloned() else {
+                    continue;
+                };
+                target.frames.insert(frame, images.add(copy));
+                unlinked.push((frame, *first));
+            }
+            Some(_) => (),
+        }
+    }
+
+    let mut frames = layer.frames.iter().collect::<Vec<_>>();
+    frames.sort_by_key(|(frame, _)| **frame);
+
+    // linked frames of `target` share an image, make sure it's only blended once
+    let mut visited = HashSet::new();
+    let mut old = Vec::new();
+    for (frame, handle) in frames {
+        if let Some(target_handle) = target.frames.get(frame) {
+            if !visited.insert(target_handle.id()) {
+                continue;
+            }
+        }
+        let Some(src) = images.get(handle).map(|image| image.data.clone()) else {
+            continue;
+        };
+        match target
+            .frames
+            .get(frame)
+            .and_then(|handle| images.get_mut(handle))
+        {
+            Some(image) => {
+                old.push((*frame, Some(image.data.clone())));
+                blend(&mut image.data, &src, layer.opacity, layer.blend_mode);
+            }
+            None => {
+                let mut image = crate::image(width, height, Color::NONE);
+                blend(&mut image.data, &src, layer.opacity, layer.blend_mode);
+                target.frames.insert(*frame, images.add(image));
+                old.push((*frame, None));
+            }
+        }
+    }
+    (old, unlinked)
 }
 
 impl Default for Layer {
     fn default() -> Self {
         Layer {
+            name: String::from("Layer"),
             frames: HashMap::new(),
             timeline_id: None,
+            visible: true,
+            locked: false,
+            opacity: 1.0,
+            blend_mode: BlendMode::Normal,
+            onion_skin: true,
         }
     }
 }
 
-#[derive(Bundle, Default)]
+/// a frame picked by [`ActiveFrame::target`], a stroke keeps drawing on it when the active
+/// layer or frame changes before the stroke ends.
+#[derive(Clone)]
+pub struct Target {
+    pub layer_id: Entity,
+    pub frame: i32,
+    pub handle: Handle<Image>,
+    /// the name of the layer, for the history
+    pub name: String,
+}
+
+/// the frame of the active layer at the current frame of the [`GlobalTimeline`], this is
+/// what the tools draw on.
+#[derive(SystemParam)]
+pub struct ActiveFrame<'w, 's> {
+    canvas: Res<'w, Canvas>,
+    global_timeline: Res<'w, GlobalTimeline>,
+    layers: Query<'w, 's, &'static mut Layer>,
+    timelines: Query<'w, 's, &'static Timeline>,
+}
+
+impl ActiveFrame<'_, '_> {
+    pub fn layer_id(&self) -> Entity {
+        self.canvas.layer_id
+    }
+
+    pub fn layer(&self) -> &Layer {
+        self.layers.get(self.canvas.layer_id).unwrap()
+    }
+
+    pub fn layer_mut(&mut self) -> Mut<Layer> {
+        self.layers.get_mut(self.canvas.layer_id).unwrap()
+    }
+
+    pub fn frame(&self) -> i32 {
+        let frame = self.global_timeline.current_frame();
+        self.layers
+            .get(self.canvas.layer_id)
+            .ok()
+            .and_then(|layer| self.timelines.get(layer.timeline_id?).ok())
+            .map_or(frame, |timeline| timeline.local_frame(frame))
+    }
+
+    /// the image of the active frame, an empty cel gets a new transparent image.
+    pub fn handle(&mut self, images: &mut Assets<Image>) -> Handle<Image> {
+        let frame = self.frame();
+        let layer = self.layers.get(self.canvas.layer_id).unwrap();
+        if let Some(handle) = layer.frames.get(&frame) {
+            return handle.clone();
+        }
+
+        let image = crate::image(self.canvas.width, self.canvas.height, Color::NONE);
+        let handle = images.add(image);
+        let mut layer = self.layers.get_mut(self.canvas.layer_id).unwrap();
+        layer.frames.insert(frame, handle.clone());
+        handle
+    }
+
+    /// the active frame, and the image it has now, for a stroke to hold on to.
+    pub fn target(&mut self, images: &mut Assets<Image>) -> Target {
+        let handle = self.handle(images);
+        Target {
+            layer_id: self.layer_id(),
+            frame: self.frame(),
+            handle,
+            name: self.layer().name.clone(),
+        }
+    }
+}
+
+/// layers aren't rendered by themselves, the texture is the frame that's drawn in the
+/// [`Composite`](crate::composite::Composite).
+#[derive(Bundle)]
 pub struct LayerBundle {
     pub layer: Layer,
     pub transform: Transform,
@@ -34,3 +336,711 @@ pub struct LayerBundle {
     pub visibility: Visibility,
     pub computed_visibility: ComputedVisibility,
 }
+
+impl Default for LayerBundle {
+    fn default() -> Self {
+        Self {
+            layer: Layer::default(),
+            transform: Transform::default(),
+            sprite: Sprite::default(),
+            texture: Handle::default(),
+            global_transform: GlobalTransform::default(),
+            visibility: Visibility::Hidden,
+            computed_visibility: ComputedVisibility::default(),
+        }
+    }
+}
+
+impl LayerBundle {
+    pub fn new(layer: Layer) -> Self {
+        Self {
+            texture: layer.frames.get(&0).cloned().unwrap_or_default(),
+            layer,
+            ..Default::default()
+        }
+    }
+}
+
+/// stack the layers of every timeline in order, the first layer is at the bottom.
+///
+/// the layers in a group are stacked between the group and the node above it.
+pub fn layer_z_order(
+    timelines: Query<&Timeline, Changed<Timeline>>,
+    groups: Query<&Children, (With<LayerGroup>, Changed<Children>)>,
+    mut transforms: Query<&mut Transform, Or<(With<Layer>, With<LayerGroup>)>>,
+) {
+    for timeline in &timelines {
+        for (i, layer_id) in timeline.layers().iter().enumerate() {
+            if let Ok(mut trans) = transforms.get_mut(*layer_id) {
+                trans.translation.z = 1.0 + i as f32;
+            }
+        }
+    }
+    for children in &groups {
+        for (i, child) in children.iter().enumerate() {
+            if let Ok(mut trans) = transforms.get_mut(*child) {
+                trans.translation.z = (i + 1) as f32 / (children.len() + 1) as f32;
+            }
+        }
+    }
+}
+
+/// [n] : new layer above the active one
+/// [ctrl + j] : duplicate the active layer
+/// [ctrl + d] : merge the active layer into the one below
+/// [delete] : delete the active layer
+/// [page up / page down] : select the layer above / below
+/// [shift + page up / page down] : move the active layer up / down
+/// [h] : show / hide the active layer
+/// [ctrl + l] : lock / unlock the active layer
+/// [- / =] : decrease / increase the opacity of the active layer
+/// [m] : cycle through blend modes
+pub fn layer_input(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut canvas: ResMut<Canvas>,
+    mut layers: Query<&mut Layer>,
+    groups: Query<(&LayerGroup, Option<&Children>)>,
+    parents: Query<&Parent>,
+    mut timelines: Query<&mut Timeline>,
+    mut images: ResMut<Assets<Image>>,
+    mut history: ResMut<History>,
+    move_state: Res<MoveState>,
+) {
+    let Some(timeline_id) = layers
+        .get(canvas.layer_id)
+        .ok()
+        .and_then(|layer| layer.timeline_id)
+    else {
+        return;
+    };
+    let container = Container::of(canvas.layer_id, timeline_id, &parents);
+    let nodes = container.nodes(&groups, &timelines);
+    let Some(index) = nodes.iter().position(|id| *id == canvas.layer_id) else {
+        return;
+    };
+
+    // every layer in drawing order, used to step through the layers
+    let all = timelines
+        .get(timeline_id)
+        .map(|timeline| ordered_layers(timeline.layers(), &groups, false))
+        .unwrap_or_default();
+    let expanded = timelines
+        .get(timeline_id)
+        .map(|timeline| ordered_layers(timeline.layers(), &groups, true))
+        .unwrap_or_default();
+    let ordered = if expanded.contains(&canvas.layer_id) {
+        expanded
+    } else {
+        all.clone()
+    };
+    let ordered_index = ordered
+        .iter()
+        .position(|id| *id == canvas.layer_id)
+        .unwrap_or(0);
+
+    let shift = input.pressed(KeyCode::LShift);
+    let ctrl = input.pressed(KeyCode::LControl);
+    let alt = input.pressed(KeyCode::LAlt);
+    let name = layers.get(canvas.layer_id).unwrap().name.clone();
+
+    // merging or copying would take the pixels the move tool is holding with it
+    if move_state.is_floating()
+        && ctrl
+        && (input.just_pressed(KeyCode::D) || !shift && input.just_pressed(KeyCode::J))
+    {
+        warn!("put the moved pixels down before merging or duplicating the layer");
+        return;
+    }
+
+    {
+        let mut layer = layers.get_mut(canvas.layer_id).unwrap();
+        let before = NodeProperties::of_layer(&layer);
+        if !ctrl && input.just_pressed(KeyCode::H) {
+            layer.visible = !layer.visible;
+            info!("{} visible: {}", layer.name, layer.visible);
+        }
+        if ctrl && input.just_pressed(KeyCode::L) {
+            layer.locked = !layer.locked;
+            info!("{} locked: {}", layer.name, layer.locked);
+        }
+        if !ctrl && !alt && input.just_pressed(KeyCode::Minus) {
+            layer.opacity = (layer.opacity - 0.1).max(0.0);
+            info!("{} opacity: {:.1}", layer.name, layer.opacity);
+        }
+        if !ctrl && !alt && input.just_pressed(KeyCode::Equals) {
+            layer.opacity = (layer.opacity + 0.1).min(1.0);
+            info!("{} opacity: {:.1}", layer.name, layer.opacity);
+        }
+        if !ctrl && input.just_pressed(KeyCode::M) {
+            layer.blend_mode = layer.blend_mode.next();
+            info!("{} blend mode: {:?}", layer.name, layer.blend_mode);
+        }
+        let after = NodeProperties::of_layer(&layer);
+        if after != before {
+            history.add(
+                before.label(&after, &layer.name),
+                HistoryItem::Properties(canvas.layer_id, before),
+            );
+        }
+    }
+
+    if !ctrl && !alt && input.just_pressed(KeyCode::N) {
+        let image = images.add(crate::image(canvas.width, canvas.height, Color::NONE));
+        let new_name = format!("Layer {}", layers.iter().count() + 1);
+        let label = format!("New layer {}", new_name);
+        let layer = commands
+            .spawn(LayerBundle::new(Layer::new(
+                new_name,
+                image,
+                Some(timeline_id),
+            )))
+            .id();
+
+        container.insert(index + 1, layer, &mut commands, &mut timelines);
+        history.add(
+            label,
+            HistoryItem::Compound(
+                String::from("new layer"),
+                vec![
+                    HistoryItem::Nodes {
+                        nodes: vec![(layer, container, index + 1)],
+                        attached: true,
+                    },
+                    HistoryItem::ActiveLayer(canvas.layer_id),
+                ],
+            ),
+        );
+        canvas.layer_id = layer;
+        info!("added layer");
+    }
+
+    if ctrl && !shift && input.just_pressed(KeyCode::J) {
+        let layer = layers.get(canvas.layer_id).unwrap().duplicate(&mut images);
+        let layer = commands.spawn(LayerBundle::new(layer)).id();
+
+        container.insert(index + 1, layer, &mut commands, &mut timelines);
+        history.add(
+            format!("Duplicate {}", name),
+            HistoryItem::Compound(
+                String::from("duplicate layer"),
+                vec![
+                    HistoryItem::Nodes {
+                        nodes: vec![(layer, container, index + 1)],
+                        attached: true,
+                    },
+                    HistoryItem::ActiveLayer(canvas.layer_id),
+                ],
+            ),
+        );
+        canvas.layer_id = layer;
+        info!("duplicated layer");
+    }
+
+    if ctrl && input.just_pressed(KeyCode::D) {
+        let Some(below) = index
+            .checked_sub(1)
+            .map(|i| nodes[i])
+            .filter(|id| layers.contains(*id))
+        else {
+            warn!("there's no layer below to merge into");
+            return;
+        };
+
+        let [layer, mut target] = layers.get_many_mut([canvas.layer_id, below]).unwrap();
+        let (frames, unlinked) = merge_layer(
+            &layer,
+            &mut target,
+            &mut images,
+            canvas.width,
+            canvas.height,
+        );
+
+        container.detach(canvas.layer_id, &mut commands, &mut timelines, &groups);
+        // undoing puts the pixels back before the unlinked frames are linked again
+        let mut items = vec![HistoryItem::Frames(below, frames)];
+        items.extend(unlinked.into_iter().map(|(frame, to)| HistoryItem::Linked {
+            layer: below,
+            frame,
+            to,
+            data: None,
+            linked: false,
+        }));
+        items.push(HistoryItem::Nodes {
+            nodes: vec![(canvas.layer_id, container, index)],
+            attached: false,
+        });
+        items.push(HistoryItem::ActiveLayer(canvas.layer_id));
+        history.add(
+            format!("Merge {} down", name),
+            HistoryItem::Compound(String::from("merge down"), items),
+        );
+        canvas.layer_id = below;
+        info!("merged layer down");
+        return;
+    }
+
+    if !ctrl && !alt && input.just_pressed(KeyCode::Delete) && all.len() > 1 {
+        let i = all
+            .iter()
+            .position(|id| *id == canvas.layer_id)
+            .unwrap_or(0);
+        let next = if i > 0 { all[i - 1] } else { all[i + 1] };
+
+        // it's kept around so the deletion can be undone
+        container.detach(canvas.layer_id, &mut commands, &mut timelines, &groups);
+        history.add(
+            format!("Delete {}", name),
+            HistoryItem::Compound(
+                String::from("delete layer"),
+                vec![
+                    HistoryItem::Nodes {
+                        nodes: vec![(canvas.layer_id, container, index)],
+                        attached: false,
+                    },
+                    HistoryItem::ActiveLayer(canvas.layer_id),
+                ],
+            ),
+        );
+        canvas.layer_id = next;
+        info!("deleted layer");
+    }
+
+    if !ctrl && input.just_pressed(KeyCode::PageUp) {
+        if shift {
+            if index + 1 < nodes.len() {
+                container.insert(index + 1, canvas.layer_id, &mut commands, &mut timelines);
+                history.add(
+                    format!("Move {} up", name),
+                    HistoryItem::Order(container, nodes.clone()),
+                );
+            }
+        } else if ordered_index + 1 < ordered.len() {
+            canvas.layer_id = ordered[ordered_index + 1];
+        }
+    }
+
+    if !ctrl && input.just_pressed(KeyCode::PageDown) {
+        if shift {
+            if index > 0 {
+                container.insert(index - 1, canvas.layer_id, &mut commands, &mut timelines);
+                history.add(
+                    format!("Move {} down", name),
+                    HistoryItem::Order(container, nodes.clone()),
+                );
+            }
+        } else if ordered_index > 0 {
+            canvas.layer_id = ordered[ordered_index - 1];
+        }
+    }
+}
+
+/// [ctrl + f] : flatten the visible layers and groups into a single layer
+pub fn flatten_input(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut canvas: ResMut<Canvas>,
+    mut set: ParamSet<(LayerTree, Query<&mut Timeline>)>,
+    groups: Query<(&LayerGroup, Option<&Children>)>,
+    mut images: ResMut<Assets<Image>>,
+    mut history: ResMut<History>,
+    move_state: Res<MoveState>,
+) {
+    if !(input.pressed(KeyCode::LControl) && input.just_pressed(KeyCode::F)) {
+        return;
+    }
+    if move_state.is_floating() {
+        warn!("put the moved pixels down before flattening");
+        return;
+    }
+
+    let tree = set.p0();
+    let Some((timeline_id, timeline)) = tree
+        .layers
+        .get(canvas.layer_id)
+        .ok()
+        .and_then(|(layer, _)| layer.timeline_id)
+        .and_then(|id| Some((id, tree.timelines.get(id).ok()?)))
+    else {
+        return;
+    };
+
+    // hidden layers and groups are left as they are
+    let visible = timeline
+        .layers()
+        .iter()
+        .copied()
+        .filter(|id| match tree.layers.get(*id) {
+            Ok((layer, _)) => layer.visible,
+            Err(_) => tree
+                .groups
+                .get(*id)
+                .map_or(false, |(group, _)| group.visible),
+        })
+        .collect::<Vec<_>>();
+    if visible.is_empty() {
+        warn!("there are no visible layers to flatten");
+        return;
+    }
+    let removed = visible
+        .iter()
+        .map(|id| {
+            (
+                *id,
+                Container::Timeline(timeline_id),
+                timeline.index_of(*id).unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let index = removed[0].2;
+
+    let frame_ids = ordered_layers(&visible, &tree.groups, false)
+        .into_iter()
+        .filter_map(|id| tree.layers.get(id).ok())
+        .flat_map(|(layer, _)| layer.frames.keys().copied())
+        .collect::<HashSet<_>>();
+    let frames = frame_ids
+        .into_iter()
+        .map(|frame| {
+            let mut data = vec![0; (canvas.width * canvas.height * 4) as usize];
+            tree.flatten_nodes(&mut data, &visible, Some(frame), &images);
+            let mut image = crate::image(canvas.width, canvas.height, Color::NONE);
+            image.data = data;
+            (frame, images.add(image))
+        })
+        .collect();
+
+    let layer = commands
+        .spawn(LayerBundle::new(Layer {
+            name: String::from("Flattened"),
+            frames,
+            timeline_id: Some(timeline_id),
+            ..Default::default()
+        }))
+        .id();
+
+    let container = Container::Timeline(timeline_id);
+    let mut timelines = set.p1();
+    for (node, ..) in removed.iter().rev() {
+        container.detach(*node, &mut commands, &mut timelines, &groups);
+    }
+    container.insert(index, layer, &mut commands, &mut timelines);
+
+    history.add(
+        format!("Flatten {} layers", visible.len()),
+        HistoryItem::Compound(
+            String::from("flatten"),
+            vec![
+                HistoryItem::Nodes {
+                    nodes: vec![(layer, container, index)],
+                    attached: true,
+                },
+                HistoryItem::Nodes {
+                    nodes: removed,
+                    attached: false,
+                },
+                HistoryItem::ActiveLayer(canvas.layer_id),
+            ],
+        ),
+    );
+    canvas.layer_id = layer;
+    info!("flattened {} layers", visible.len());
+}
+
+/// [alt + l] : link the current frame of the active layer to the frame before it
+/// [alt + shift + l] : unlink the current frame, it gets its own copy of the image
+///
+/// linked frames share one image, drawing on one of them changes all of them.
+pub fn link_input(
+    input: Res<Input<KeyCode>>,
+    mut active: ActiveFrame,
+    mut images: ResMut<Assets<Image>>,
+    mut history: ResMut<History>,
+    move_state: Res<MoveState>,
+) {
+    if !(input.pressed(KeyCode::LAlt) && input.just_pressed(KeyCode::L)) {
+        return;
+    }
+    if move_state.is_floating() {
+        warn!("put the moved pixels down before linking the frame");
+        return;
+    }
+    let frame = active.frame();
+    let layer_id = active.layer_id();
+    let mut layer = active.layer_mut();
+
+    if input.pressed(KeyCode::LShift) {
+        let Some(&to) = layer.linked_frames(frame).first() else {
+            warn!("frame {} isn't linked", frame);
+            return;
+        };
+        let copy = images.get(&layer.frames[&frame]).unwrap().clone();
+        layer.frames.insert(frame, images.add(copy));
+        history.add(
+            format!("Unlink frame {} of {}", frame, layer.name),
+            HistoryItem::Linked {
+                layer: layer_id,
+                frame,
+                to,
+                data: None,
+                linked: false,
+            },
+        );
+        info!("unlinked frame {}", frame);
+    } else {
+        let to = frame - 1;
+        let Some(target) = layer.frames.get(&to).cloned() else {
+            warn!("there's no image before frame {} to link to", frame);
+            return;
+        };
+        if layer.frames.get(&frame) == Some(&target) {
+            return;
+        }
+        // an empty frame stays `None`, undoing empties it again
+        let old = layer
+            .frames
+            .get(&frame)
+            .and_then(|handle| images.get(handle))
+            .map(|image| image.data.clone());
+        layer.frames.insert(frame, target);
+        history.add(
+            format!("Link frame {} of {} to {}", frame, layer.name, to),
+            HistoryItem::Linked {
+                layer: layer_id,
+                frame,
+                to,
+                data: old,
+                linked: true,
+            },
+        );
+        info!("linked frame {} to {}", frame, to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+    use crate::{group::Detached, selection::Selection, ColorPalette, Document};
+
+    const CLEAR: [u8; 4] = [0; 4];
+    const GRAY: [u8; 4] = [100, 100, 100, 255];
+    const RED: [u8; 4] = [200, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 200, 255];
+
+    /// a 2 x 1 canvas with a timeline, `layers` go in it bottom first.
+    fn app(layers: Vec<(Layer, Vec<(i32, [[u8; 4]; 2])>)>) -> (App, Entity, Vec<Entity>) {
+        let mut app = App::new();
+        app.add_plugins(AssetPlugin::default())
+            .add_asset::<Image>()
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<History>()
+            .init_resource::<MoveState>()
+            .init_resource::<Selection>()
+            .init_resource::<ColorPalette>()
+            .init_resource::<GlobalTimeline>();
+
+        let timeline = app.world.spawn_empty().id();
+        let mut ids = Vec::new();
+        for (mut layer, frames) in layers {
+            for (frame, pixels) in frames {
+                let handle = image(&mut app, pixels);
+                layer.frames.insert(frame, handle);
+            }
+            layer.timeline_id = Some(timeline);
+            ids.push(app.world.spawn(LayerBundle::new(layer)).id());
+        }
+        app.world
+            .entity_mut(timeline)
+            .insert(Timeline::new(ids.clone(), 0));
+        app.insert_resource(Canvas::new(2, 1, *ids.last().unwrap()));
+        (app, timeline, ids)
+    }
+
+    fn image(app: &mut App, pixels: [[u8; 4]; 2]) -> Handle<Image> {
+        let mut image = crate::image(2, 1, Color::NONE);
+        image.data = pixels.concat();
+        app.world.resource_mut::<Assets<Image>>().add(image)
+    }
+
+    fn pixels(app: &App, layer: Entity, frame: i32) -> Option<Vec<u8>> {
+        let handle = app.world.get::<Layer>(layer)?.frames.get(&frame)?;
+        let images = app.world.resource::<Assets<Image>>();
+        images.get(handle).map(|image| image.data.clone())
+    }
+
+    fn press(app: &mut App, keys: impl IntoIterator<Item = KeyCode>) {
+        let mut input = app.world.resource_mut::<Input<KeyCode>>();
+        for key in keys {
+            input.press(key);
+        }
+    }
+
+    fn undo(app: &mut App) {
+        let mut state = SystemState::<(ResMut<History>, Document)>::new(&mut app.world);
+        let (mut history, mut doc) = state.get_mut(&mut app.world);
+        assert!(history.undo(&mut doc).is_some());
+        state.apply(&mut app.world);
+    }
+
+    #[test]
+    fn merge_down() {
+        // frames 0, 1 and 2 of the bottom layer are linked
+        let (mut app, timeline, ids) = app(vec![
+            (Layer::default(), vec![(0, [GRAY, CLEAR])]),
+            (
+                Layer::default(),
+                vec![(0, [RED, CLEAR]), (1, [CLEAR, BLUE]), (3, [BLUE, BLUE])],
+            ),
+        ]);
+        let (bottom, top) = (ids[0], ids[1]);
+        {
+            let mut layer = app.world.get_mut::<Layer>(bottom).unwrap();
+            let shared = layer.frames[&0].clone();
+            layer.frames.insert(1, shared.clone());
+            layer.frames.insert(2, shared);
+        }
+        app.add_systems(Update, layer_input);
+        press(&mut app, [KeyCode::LControl, KeyCode::D]);
+        app.update();
+
+        assert_eq!(pixels(&app, bottom, 0), Some([RED, CLEAR].concat()));
+        assert_eq!(pixels(&app, bottom, 1), Some([GRAY, BLUE].concat()));
+        // nothing was merged on frame 2, it keeps what it had
+        assert_eq!(pixels(&app, bottom, 2), Some([GRAY, CLEAR].concat()));
+        assert_eq!(pixels(&app, bottom, 3), Some([BLUE, BLUE].concat()));
+        let layer = app.world.get::<Layer>(bottom).unwrap();
+        assert!(layer.linked_frames(0).is_empty());
+        assert!(layer.linked_frames(1).is_empty());
+        assert_eq!(
+            app.world.get::<Timeline>(timeline).unwrap().layers(),
+            [bottom]
+        );
+        assert_eq!(app.world.resource::<Canvas>().layer_id, bottom);
+
+        undo(&mut app);
+        for frame in 0..3 {
+            assert_eq!(pixels(&app, bottom, frame), Some([GRAY, CLEAR].concat()));
+        }
+        assert_eq!(pixels(&app, bottom, 3), None);
+        let layer = app.world.get::<Layer>(bottom).unwrap();
+        assert_eq!(layer.linked_frames(0), [1, 2]);
+        assert_eq!(
+            app.world.get::<Timeline>(timeline).unwrap().layers(),
+            [bottom, top]
+        );
+        assert!(app.world.get::<Detached>(top).is_none());
+        assert_eq!(app.world.resource::<Canvas>().layer_id, top);
+    }
+
+    #[test]
+    fn flatten() {
+        let hidden = Layer {
+            visible: false,
+            ..Default::default()
+        };
+        let half = Layer {
+            opacity: 0.5,
+            ..Default::default()
+        };
+        let (mut app, timeline, ids) = app(vec![
+            (Layer::default(), vec![(0, [GRAY, CLEAR])]),
+            (hidden, vec![(0, [RED, RED])]),
+            (half, vec![(0, [RED, BLUE]), (1, [CLEAR, BLUE])]),
+        ]);
+        app.add_systems(Update, flatten_input);
+        press(&mut app, [KeyCode::LControl, KeyCode::F]);
+        app.update();
+
+        let layers = app
+            .world
+            .get::<Timeline>(timeline)
+            .unwrap()
+            .layers()
+            .to_vec();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[1], ids[1], "the hidden layer is left as it is");
+        let flat = layers[0];
+        assert_eq!(
+            pixels(&app, flat, 0),
+            Some([[150, 50, 50, 255], [0, 0, 200, 128]].concat())
+        );
+        assert_eq!(
+            pixels(&app, flat, 1),
+            Some([CLEAR, [0, 0, 200, 128]].concat())
+        );
+        assert!(app.world.get::<Detached>(ids[0]).is_some());
+        assert!(app.world.get::<Detached>(ids[2]).is_some());
+
+        undo(&mut app);
+        assert_eq!(app.world.get::<Timeline>(timeline).unwrap().layers(), ids);
+        assert!(app.world.get::<Detached>(flat).is_some());
+        assert!(ids
+            .iter()
+            .all(|id| app.world.get::<Detached>(*id).is_none()));
+        assert_eq!(app.world.resource::<Canvas>().layer_id, ids[2]);
+    }
+
+    #[test]
+    fn duplicate() {
+        let (mut app, timeline, ids) = app(vec![(
+            Layer {
+                name: String::from("Layer 1"),
+                opacity: 0.5,
+                ..Default::default()
+            },
+            vec![(0, [RED, CLEAR]), (2, [GRAY, BLUE])],
+        )]);
+        app.add_systems(Update, layer_input);
+        press(&mut app, [KeyCode::LControl, KeyCode::J]);
+        app.update();
+
+        let layers = app
+            .world
+            .get::<Timeline>(timeline)
+            .unwrap()
+            .layers()
+            .to_vec();
+        assert_eq!(layers.len(), 2);
+        let copy = layers[1];
+        assert_eq!(app.world.resource::<Canvas>().layer_id, copy);
+        for frame in 0..3 {
+            assert_eq!(pixels(&app, copy, frame), pixels(&app, ids[0], frame));
+        }
+        let (layer, original) = (
+            app.world.get::<Layer>(copy).unwrap(),
+            app.world.get::<Layer>(ids[0]).unwrap(),
+        );
+        assert_eq!(layer.name, "Layer 1 copy");
+        assert_eq!(layer.opacity, 0.5);
+        // drawing on the copy doesn't change the original
+        assert_ne!(layer.frames[&0], original.frames[&0]);
+
+        undo(&mut app);
+        assert_eq!(app.world.get::<Timeline>(timeline).unwrap().layers(), ids);
+        assert!(app.world.get::<Detached>(copy).is_some());
+        assert_eq!(app.world.resource::<Canvas>().layer_id, ids[0]);
+    }
+
+    #[test]
+    fn link_an_empty_frame() {
+        let (mut app, timeline, ids) = app(vec![(Layer::default(), vec![(0, [RED, BLUE])])]);
+        // global frame 0 is frame 1 of the layer
+        app.world
+            .entity_mut(timeline)
+            .insert(Timeline::new(ids.clone(), -1));
+        app.add_systems(Update, link_input);
+        press(&mut app, [KeyCode::LAlt, KeyCode::L]);
+        app.update();
+
+        let layer = app.world.get::<Layer>(ids[0]).unwrap();
+        assert_eq!(layer.frames.get(&1), layer.frames.get(&0));
+
+        // the frame is empty again, not a transparent image
+        undo(&mut app);
+        let layer = app.world.get::<Layer>(ids[0]).unwrap();
+        assert!(!layer.frames.contains_key(&1));
+        assert_eq!(pixels(&app, ids[0], 0), Some([RED, BLUE].concat()));
+    }
+}
@@ -1,20 +1,33 @@
 #![feature(drain_filter, array_chunks)]
 
-use bevy::prelude::*;
+use std::time::SystemTime;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
 use canvas::Canvas;
-use layer::Layer;
-use tools::BrushState;
+use composite::BlendMode;
+use delta::Delta;
+use group::{Container, LayerGroup};
+use layer::{Cel, Layer};
+use selection::Selection;
+use serde::{Deserialize, Serialize};
+use timeline::{Tag, Timeline};
+use tools::{BrushState, MoveState};
 
 pub mod camera;
 pub mod canvas;
+pub mod composite;
+pub mod delta;
+pub mod file;
+pub mod group;
+pub mod history;
 pub mod layer;
+pub mod onion;
+pub mod resize;
+pub mod selection;
 pub mod timeline;
 pub mod tools;
 
-pub const WIDTH: u32 = 512;
-pub const HEIGHT: u32 = 512;
-
-#[derive(Resource, Default)]
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
 pub struct ColorPalette {
     pub palette: Vec<Color>,
     pub color_state: ColorState,
@@ -36,6 +49,11 @@ impl ColorPalette {
         }
     }
     pub fn set_primary(&mut self, index: u8) {
+        // colors can be removed from the palette
+        if index as usize >= self.palette.len() {
+            warn!("the palette has no color {}", index);
+            return;
+        }
         match &mut self.color_state {
             ColorState::Indexed { primary, .. } => *primary = index,
             ColorState::Color { primary, .. } => *primary = self.palette[index as usize],
@@ -43,6 +61,7 @@ impl ColorPalette {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub enum ColorState {
     Indexed { primary: u8, secondary: u8 },
     Color { primary: Color, secondary: Color },
@@ -69,68 +88,579 @@ pub enum ToolState {
     Idle,
 }
 
-#[derive(Resource, Default)]
+/// how much memory the history uses by default, 64 MiB.
+pub const DEFAULT_HISTORY_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Resource, Clone)]
 pub struct History {
-    pub past: Vec<HistoryItem>,
-    pub future: Vec<HistoryItem>,
+    pub past: Vec<HistoryEntry>,
+    pub future: Vec<HistoryEntry>,
+    /// the most bytes the history can use, the oldest entries are dropped to stay under it
     max_size: usize,
 }
 
+impl Default for History {
+    fn default() -> Self {
+        History::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
 impl History {
-    pub fn add(&mut self, item: HistoryItem) {
-        self.past.push(item);
+    pub fn new(max_size: usize) -> Self {
+        History {
+            past: Vec::new(),
+            future: Vec::new(),
+            max_size,
+        }
+    }
+
+    /// `label` says what happened, like "Brush stroke on Layer 2, frame 4".
+    pub fn add(&mut self, label: impl Into<String>, item: HistoryItem) {
+        self.past.push(HistoryEntry {
+            label: label.into(),
+            time: SystemTime::now(),
+            item,
+        });
         self.future.clear();
+        self.trim();
+    }
+
+    /// every entry from the oldest to the newest, the first [`position`](Self::position)
+    /// of them are done.
+    pub fn entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.past.iter().chain(self.future.iter().rev())
+    }
+
+    pub fn len(&self) -> usize {
+        self.past.len() + self.future.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// how many entries are done, 0 is the state before the oldest entry.
+    pub fn position(&self) -> usize {
+        self.past.len()
+    }
+
+    /// revert the last entry, returns it or `None` if there's nothing to undo.
+    pub fn undo(&mut self, doc: &mut Document) -> Option<&HistoryEntry> {
+        let mut entry = self.past.pop()?;
+        entry.item.swap(doc);
+        self.future.push(entry);
+        self.future.last()
+    }
+
+    /// redo the last undone entry, returns it or `None` if there's nothing to redo.
+    pub fn redo(&mut self, doc: &mut Document) -> Option<&HistoryEntry> {
+        let mut entry = self.future.pop()?;
+        entry.item.swap(doc);
+        self.past.push(entry);
+        self.past.last()
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.trim();
+    }
+
+    /// how many bytes every entry takes up together.
+    pub fn size(&self) -> usize {
+        self.past
+            .iter()
+            .chain(&self.future)
+            .map(HistoryEntry::size)
+            .sum()
+    }
+
+    /// drop entries until the history fits in `max_size`, the redo steps furthest away go first,
+    /// then the oldest undo steps. the last entry is always kept.
+    fn trim(&mut self) {
+        let mut size = self.size();
+        let mut undone = 0;
+        while size > self.max_size && undone < self.future.len() && self.len() - undone > 1 {
+            size -= self.future[undone].size();
+            undone += 1;
+        }
+        if undone > 0 {
+            self.future.drain(..undone);
+            info!("dropped the {undone} furthest redo steps, the history uses {size} bytes");
+        }
+
+        let mut dropped = 0;
+        while size > self.max_size && dropped + 1 < self.past.len() {
+            size -= self.past[dropped].size();
+            dropped += 1;
+        }
+        if dropped > 0 {
+            self.past.drain(..dropped);
+            info!("dropped the {dropped} oldest undo steps, the history uses {size} bytes");
+        }
     }
 }
 
+/// a [`HistoryItem`] and when it happened.
+#[derive(Clone)]
+pub struct HistoryEntry {
+    pub label: String,
+    pub time: SystemTime,
+    pub item: HistoryItem,
+}
+
+impl HistoryEntry {
+    pub fn size(&self) -> usize {
+        self.label.len() + self.item.size()
+    }
+}
+
+/// the settings of a layer or group that change without touching its pixels.
+#[derive(Clone, Copy, PartialEq)]
+pub struct NodeProperties {
+    pub visible: bool,
+    /// always `false` for groups
+    pub locked: bool,
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+}
+
+impl NodeProperties {
+    pub fn of_layer(layer: &Layer) -> Self {
+        NodeProperties {
+            visible: layer.visible,
+            locked: layer.locked,
+            opacity: layer.opacity,
+            blend_mode: layer.blend_mode,
+        }
+    }
+
+    pub fn of_group(group: &LayerGroup) -> Self {
+        NodeProperties {
+            visible: group.visible,
+            locked: false,
+            opacity: group.opacity,
+            blend_mode: group.blend_mode,
+        }
+    }
+
+    /// what changed between these properties and `after`, like "Hide Layer 2".
+    pub fn label(&self, after: &NodeProperties, name: &str) -> String {
+        if self.visible != after.visible {
+            let verb = if after.visible { "Show" } else { "Hide" };
+            format!("{verb} {name}")
+        } else if self.locked != after.locked {
+            let verb = if after.locked { "Lock" } else { "Unlock" };
+            format!("{verb} {name}")
+        } else if self.opacity != after.opacity {
+            format!("Opacity of {name} to {:.1}", after.opacity)
+        } else {
+            format!("Blend mode of {name} to {:?}", after.blend_mode)
+        }
+    }
+
+    fn swap_layer(&mut self, layer: &mut Layer) {
+        std::mem::swap(&mut self.visible, &mut layer.visible);
+        std::mem::swap(&mut self.locked, &mut layer.locked);
+        std::mem::swap(&mut self.opacity, &mut layer.opacity);
+        std::mem::swap(&mut self.blend_mode, &mut layer.blend_mode);
+    }
+
+    fn swap_group(&mut self, group: &mut LayerGroup) {
+        std::mem::swap(&mut self.visible, &mut group.visible);
+        std::mem::swap(&mut self.opacity, &mut group.opacity);
+        std::mem::swap(&mut self.blend_mode, &mut group.blend_mode);
+    }
+}
+
+#[derive(Clone)]
 pub enum HistoryItem {
-    Painted(Vec<u8>),
-    Filled(Vec<u8>),
+    /// the layer, the frame and the pixels that were painted
+    Painted(Entity, i32, Delta),
+    Filled(Entity, i32, Delta),
+    /// the selection mask before it changed, see [`Selection`]
     Selected(Vec<u8>),
+    /// the canvas size and every frame of every layer before a resize, crop or trim
+    Resized {
+        width: u32,
+        height: u32,
+        frames: Vec<(Entity, i32, Vec<u8>)>,
+    },
+    /// frames of a layer before they were changed, `None` if the frame didn't exist
+    Frames(Entity, Vec<(i32, Option<Vec<u8>>)>),
+    /// layers or groups that were put in or taken out of the document
+    Nodes {
+        /// the node, its container and its index in the container, in the order they're put back
+        nodes: Vec<(Entity, Container, usize)>,
+        /// whether the nodes are currently in the document
+        attached: bool,
+    },
+    /// the active layer before it was changed
+    ActiveLayer(Entity),
+    /// a frame inserted into or removed from every layer of a timeline
+    Frame {
+        timeline: Entity,
+        frame: i32,
+        /// the cel of each layer at `frame` while the frame is removed
+        layers: Vec<(Entity, Option<Cel>)>,
+        /// the duration of `frame` while it's removed
+        duration: u32,
+        /// the tags of the timeline before the frame was inserted or removed
+        tags: Vec<Tag>,
+        /// whether the frame is currently in the timeline
+        inserted: bool,
+    },
+    /// a frame of a layer that was linked to, or unlinked from, the image of another frame
+    Linked {
+        layer: Entity,
+        frame: i32,
+        /// the frame it's linked to
+        to: i32,
+        /// the image `frame` had before it was linked, `None` if it had none or while it's unlinked
+        data: Option<Vec<u8>>,
+        /// whether `frame` is currently linked to `to`
+        linked: bool,
+    },
+    /// two frames of every layer of a timeline that were swapped
+    FramesSwapped {
+        timeline: Entity,
+        a: i32,
+        b: i32,
+    },
+    /// the visibility, lock, opacity and blend mode of a layer or group before they changed
+    Properties(Entity, NodeProperties),
+    /// the layers and groups of a container before they were reordered
+    Order(Container, Vec<Entity>),
+    /// the frame durations and tags of a timeline before they changed
+    Animation {
+        timeline: Entity,
+        durations: Vec<u32>,
+        tags: Vec<Tag>,
+    },
+    /// the palette and the picked colors before they changed
+    Palette(ColorPalette),
+    /// several changes that are undone and redone together
+    Compound(String, Vec<HistoryItem>),
+}
+
+/// everything a [`HistoryItem`] can change.
+#[derive(SystemParam)]
+pub struct Document<'w, 's> {
+    pub commands: Commands<'w, 's>,
+    pub canvas: ResMut<'w, Canvas>,
+    pub layers: Query<'w, 's, (Entity, &'static mut Layer)>,
+    pub groups: Query<'w, 's, (&'static mut LayerGroup, Option<&'static Children>)>,
+    pub timelines: Query<'w, 's, &'static mut Timeline>,
+    pub images: ResMut<'w, Assets<Image>>,
+    pub palette: ResMut<'w, ColorPalette>,
+    pub selection: ResMut<'w, Selection>,
+}
+
+impl HistoryItem {
+    pub fn name(&self) -> &str {
+        match self {
+            HistoryItem::Painted(..) => "paint",
+            HistoryItem::Filled(..) => "fill",
+            HistoryItem::Selected(_) => "select",
+            HistoryItem::Resized { .. } => "resize",
+            HistoryItem::Frames(..) => "frames",
+            HistoryItem::Nodes { .. } => "layers",
+            HistoryItem::ActiveLayer(_) => "active layer",
+            HistoryItem::Frame { inserted: true, .. } => "insert frame",
+            HistoryItem::Frame {
+                inserted: false, ..
+            } => "delete frame",
+            HistoryItem::FramesSwapped { .. } => "move frame",
+            HistoryItem::Linked { linked: true, .. } => "link frame",
+            HistoryItem::Linked { linked: false, .. } => "unlink frame",
+            HistoryItem::Properties(..) => "layer properties",
+            HistoryItem::Order(..) => "move layers",
+            HistoryItem::Animation { .. } => "timing",
+            HistoryItem::Palette(_) => "palette",
+            HistoryItem::Compound(name, _) => name,
+        }
+    }
+
+    /// roughly how many bytes this item keeps alive.
+    pub fn size(&self) -> usize {
+        let frames = |frames: &mut dyn Iterator<Item = &Option<Vec<u8>>>| -> usize {
+            frames.map(|data| data.as_ref().map_or(0, Vec::len)).sum()
+        };
+        let tags_size = |tags: &[Tag]| -> usize {
+            tags.iter()
+                .map(|tag| std::mem::size_of::<Tag>() + tag.name.len())
+                .sum()
+        };
+
+        std::mem::size_of::<Self>()
+            + match self {
+                HistoryItem::Painted(_, _, delta) | HistoryItem::Filled(_, _, delta) => {
+                    delta.size()
+                }
+                HistoryItem::Selected(data) => data.len(),
+                HistoryItem::Resized { frames, .. } => {
+                    frames.iter().map(|(_, _, data)| data.len()).sum()
+                }
+                HistoryItem::Frames(_, data) => frames(&mut data.iter().map(|(_, data)| data)),
+                HistoryItem::Nodes { nodes, .. } => {
+                    nodes.len() * std::mem::size_of::<(Entity, Container, usize)>()
+                }
+                HistoryItem::ActiveLayer(_)
+                | HistoryItem::FramesSwapped { .. }
+                | HistoryItem::Properties(..) => 0,
+                HistoryItem::Order(_, nodes) => nodes.len() * std::mem::size_of::<Entity>(),
+                HistoryItem::Animation {
+                    durations, tags, ..
+                } => durations.len() * std::mem::size_of::<u32>() + tags_size(tags),
+                HistoryItem::Palette(palette) => {
+                    palette.palette.len() * std::mem::size_of::<Color>()
+                }
+                HistoryItem::Frame { layers, tags, .. } => {
+                    let cels = layers.iter().map(|(_, cel)| match cel {
+                        Some(Cel::Image(data)) => data.len(),
+                        _ => 0,
+                    });
+                    cels.sum::<usize>() + tags_size(tags)
+                }
+                HistoryItem::Linked { data, .. } => data.as_ref().map_or(0, Vec::len),
+                HistoryItem::Compound(name, items) => {
+                    name.len() + items.iter().map(HistoryItem::size).sum::<usize>()
+                }
+            }
+    }
+
+    /// swap the state stored in this item with the current state of the document,
+    /// so calling it a second time reverts it.
+    pub fn swap(&mut self, doc: &mut Document) {
+        let Document {
+            commands,
+            canvas,
+            layers,
+            groups,
+            timelines,
+            images,
+            palette,
+            selection,
+        } = doc;
+
+        match self {
+            HistoryItem::Painted(layer_id, frame, delta)
+            | HistoryItem::Filled(layer_id, frame, delta) => {
+                // the layer might have been deleted since
+                let Some(image) = layers
+                    .get(*layer_id)
+                    .ok()
+                    .and_then(|(_, layer)| layer.frames.get(frame))
+                    .and_then(|handle| images.get_mut(handle))
+                else {
+                    return;
+                };
+                let width = image.texture_descriptor.size.width;
+                delta.apply(&mut image.data, width);
+            }
+            HistoryItem::Resized {
+                width,
+                height,
+                frames,
+            } => {
+                for (layer_id, frame, data) in frames {
+                    let Some(image) = layers
+                        .get(*layer_id)
+                        .ok()
+                        .and_then(|(_, layer)| layer.frames.get(frame))
+                        .and_then(|handle| images.get_mut(handle))
+                    else {
+                        continue;
+                    };
+                    std::mem::swap(data, &mut image.data);
+                    resize::set_image_size(image, *width, *height);
+                }
+                std::mem::swap(width, &mut canvas.width);
+                std::mem::swap(height, &mut canvas.height);
+            }
+            HistoryItem::Frames(layer_id, frames) => {
+                let Ok((_, mut layer)) = layers.get_mut(*layer_id) else {
+                    return;
+                };
+                for (frame, data) in frames {
+                    match (layer.frames.get(frame).cloned(), data.take()) {
+                        (Some(handle), Some(mut old)) => {
+                            if let Some(image) = images.get_mut(&handle) {
+                                std::mem::swap(&mut old, &mut image.data);
+                            }
+                            *data = Some(old);
+                        }
+                        (Some(handle), None) => {
+                            layer.frames.remove(frame);
+                            *data = images.get(&handle).map(|image| image.data.clone());
+                        }
+                        (None, Some(old)) => {
+                            let mut image = crate::image(canvas.width, canvas.height, Color::NONE);
+                            image.data = old;
+                            layer.frames.insert(*frame, images.add(image));
+                        }
+                        (None, None) => (),
+                    }
+                }
+            }
+            HistoryItem::Nodes { nodes, attached } => {
+                if *attached {
+                    for (node, container, _) in nodes.iter().rev() {
+                        container.detach(*node, commands, timelines, &groups.to_readonly());
+                    }
+                } else {
+                    for (node, container, index) in nodes.iter() {
+                        container.attach(*index, *node, commands, timelines, &groups.to_readonly());
+                    }
+                }
+                *attached = !*attached;
+            }
+            HistoryItem::ActiveLayer(layer_id) => {
+                std::mem::swap(layer_id, &mut canvas.layer_id);
+            }
+            HistoryItem::Frame {
+                timeline,
+                frame,
+                layers: data,
+                duration,
+                tags,
+                inserted,
+            } => {
+                if let Ok(mut timeline) = timelines.get_mut(*timeline) {
+                    if *inserted {
+                        *duration = timeline.remove_frame(*frame);
+                    } else {
+                        timeline.insert_frame(*frame, *duration);
+                    }
+                    std::mem::swap(tags, timeline.tags_mut());
+                }
+                let timeline_layers = layers
+                    .iter_mut()
+                    .filter(|(_, layer)| layer.timeline_id == Some(*timeline));
+                if *inserted {
+                    *data = timeline_layers
+                        .map(|(layer_id, mut layer)| (layer_id, layer.take_cel(*frame, images)))
+                        .collect();
+                } else {
+                    for (layer_id, mut layer) in timeline_layers {
+                        let cel = data
+                            .iter_mut()
+                            .find(|(id, _)| *id == layer_id)
+                            .and_then(|(_, cel)| cel.take());
+                        layer.restore_cel(*frame, cel, images, canvas.width, canvas.height);
+                    }
+                    data.clear();
+                }
+                *inserted = !*inserted;
+            }
+            HistoryItem::Linked {
+                layer: layer_id,
+                frame,
+                to,
+                data,
+                linked,
+            } => {
+                let Ok((_, mut layer)) = layers.get_mut(*layer_id) else {
+                    return;
+                };
+                if *linked {
+                    // an empty frame goes back to being empty
+                    match data.take() {
+                        Some(old) => {
+                            let mut image = crate::image(canvas.width, canvas.height, Color::NONE);
+                            image.data = old;
+                            layer.frames.insert(*frame, images.add(image));
+                        }
+                        None => {
+                            layer.frames.remove(frame);
+                        }
+                    }
+                } else {
+                    let Some(target) = layer.frames.get(to).cloned() else {
+                        return;
+                    };
+                    *data = layer
+                        .frames
+                        .get(frame)
+                        .and_then(|handle| images.get(handle))
+                        .map(|image| image.data.clone());
+                    layer.frames.insert(*frame, target);
+                }
+                *linked = !*linked;
+            }
+            HistoryItem::FramesSwapped { timeline, a, b } => {
+                if let Ok(mut timeline) = timelines.get_mut(*timeline) {
+                    timeline.swap_frames(*a, *b);
+                }
+                for (_, mut layer) in layers.iter_mut() {
+                    if layer.timeline_id == Some(*timeline) {
+                        layer.swap_frames(*a, *b);
+                    }
+                }
+            }
+            HistoryItem::Properties(node, properties) => {
+                if let Ok((_, mut layer)) = layers.get_mut(*node) {
+                    properties.swap_layer(&mut layer);
+                } else if let Ok((mut group, _)) = groups.get_mut(*node) {
+                    properties.swap_group(&mut group);
+                }
+            }
+            HistoryItem::Order(container, nodes) => {
+                let current = container.nodes(&groups.to_readonly(), timelines);
+                container.set_nodes(nodes, commands, timelines);
+                *nodes = current;
+            }
+            HistoryItem::Animation {
+                timeline,
+                durations,
+                tags,
+            } => {
+                if let Ok(mut timeline) = timelines.get_mut(*timeline) {
+                    std::mem::swap(durations, timeline.durations_mut());
+                    std::mem::swap(tags, timeline.tags_mut());
+                }
+            }
+            HistoryItem::Palette(old) => {
+                std::mem::swap(old, &mut **palette);
+            }
+            HistoryItem::Selected(mask) => {
+                std::mem::swap(mask, &mut selection.mask);
+            }
+            HistoryItem::Compound(_, items) => {
+                for item in items.iter_mut() {
+                    item.swap(doc);
+                }
+                // the next swap has to go the other way
+                items.reverse();
+            }
+        }
+    }
 }
 
 pub fn undo_redo(
     mut history: ResMut<History>,
     _brush_state: ResMut<BrushState>,
     input: Res<Input<KeyCode>>,
-    canvas: Res<Canvas>,
-    layers: Query<&Layer>,
-    mut images: ResMut<Assets<Image>>,
+    move_state: Res<MoveState>,
+    mut doc: Document,
 ) {
-    let layer = layers.get(canvas.layer_id).unwrap();
-    let image = images.get_mut(&layer.frames[&0]).unwrap();
+    if move_state.is_floating() && input.any_just_pressed([KeyCode::Comma, KeyCode::Period]) {
+        warn!("put the moved pixels down before undoing");
+        return;
+    }
 
     if input.just_pressed(KeyCode::Comma) {
-        if let Some(mut item) = history.past.pop() {
-            match &mut item {
-                HistoryItem::Painted(data) => {
-                    info!("undo paint");
-                    std::mem::swap(data, &mut image.data);
-                }
-                HistoryItem::Filled(data) => {
-                    info!("undo fill");
-                    std::mem::swap(data, &mut image.data);
-                }
-                _ => unimplemented!(),
-            }
-            history.future.push(item);
+        if let Some(entry) = history.undo(&mut doc) {
+            info!("undo {}", entry.label);
         }
     }
 
     if input.just_pressed(KeyCode::Period) {
-        if let Some(mut item) = history.future.pop() {
-            match &mut item {
-                HistoryItem::Painted(data) => {
-                    info!("redo paint");
-                    std::mem::swap(data, &mut image.data);
-                }
-                HistoryItem::Filled(data) => {
-                    info!("undo fill");
-                    std::mem::swap(data, &mut image.data);
-                }
-                _ => unimplemented!(),
-            }
-            history.past.push(item);
+        if let Some(entry) = history.redo(&mut doc) {
+            info!("redo {}", entry.label);
         }
     }
 }
@@ -219,3 +749,95 @@ pub fn color_at_img_pos(pos: IVec2, width: u32, image: &[u8]) -> Color {
     let c = &image[idx..idx + 3];
     Color::rgba_u8(c[0], c[1], c[2], c[3])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(label: &str, item: HistoryItem) -> HistoryEntry {
+        HistoryEntry {
+            label: label.into(),
+            time: SystemTime::now(),
+            item,
+        }
+    }
+
+    #[test]
+    fn single_pixel_strokes_stay_within_budget() {
+        let (width, height) = (128, 128);
+        let max_size = 16 * 1024;
+        let mut history = History::new(max_size);
+        let mut image = vec![0; (width * height * 4) as usize];
+
+        for i in 0..2000 {
+            let before = image.clone();
+            let pixel = (i * 37 % (width * height)) as usize * 4;
+            image[pixel..pixel + 4].copy_from_slice(&[255, 0, 0, 255]);
+
+            let delta = Delta::new(&before, &image, width).unwrap();
+            history.add(
+                "Brush stroke",
+                HistoryItem::Painted(Entity::from_raw(0), 0, delta),
+            );
+            assert!(history.size() <= max_size);
+        }
+
+        // a stroke keeps its pixel, not the whole frame
+        let stroke = history.past.last().unwrap().size();
+        assert!(stroke < 256, "a single pixel stroke takes {stroke} bytes");
+        assert!(history.len() > max_size / stroke / 2);
+    }
+
+    #[test]
+    fn trim_drops_the_oldest_entries() {
+        let mask = || HistoryItem::Selected(vec![0; 1000]);
+        let size = entry("0", mask()).size();
+        let mut history = History::new(3 * size);
+
+        for i in 0..5 {
+            history.add(i.to_string(), mask());
+        }
+        let labels = history
+            .entries()
+            .map(|entry| entry.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["2", "3", "4"]);
+        assert_eq!(history.size(), 3 * size);
+
+        // the last entry is always kept
+        history.set_max_size(0);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.past[0].label, "4");
+    }
+
+    #[test]
+    fn trim_drops_the_furthest_redo_steps_first() {
+        let mask = || HistoryItem::Selected(vec![0; 1000]);
+        let size = entry("0", mask()).size();
+        let mut history = History::new(5 * size);
+
+        for i in 0..5 {
+            history.add(i.to_string(), mask());
+        }
+        // undo the last 3 entries, undoing doesn't need a document to be counted
+        for _ in 0..3 {
+            let entry = history.past.pop().unwrap();
+            history.future.push(entry);
+        }
+
+        history.set_max_size(3 * size);
+        let labels = history
+            .entries()
+            .map(|entry| entry.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["0", "1", "2"]);
+        assert_eq!(history.position(), 2);
+        assert!(history.size() <= history.max_size());
+
+        // with every entry undone, the next redo step is kept
+        history.future.extend(history.past.drain(..).rev());
+        history.set_max_size(0);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.future[0].label, "0");
+    }
+}
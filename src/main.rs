@@ -1,43 +1,141 @@
 #![feature(generic_const_exprs, array_chunks, exclusive_range_pattern)]
 
+use std::path::PathBuf;
+
 use bevy::{math::Vec3Swizzles, prelude::*};
 use sprinkle::{
     camera::{move_camera, setup_camera, zoom_camera},
-    canvas::{cursor_position, setup_canvas, shadow_paralax},
+    canvas::{
+        cursor_position, new_document, setup_canvas, shadow_paralax, shadow_texture, Canvas,
+        DocumentSettings,
+    },
+    composite::{setup_composite, update_composite, LayerTree},
+    file::{
+        aseprite::export_aseprite,
+        autosave::{
+            autosave, check_recovery, recovery_input, remove_recovery_on_exit, track_changes,
+            Autosave,
+        },
+        gif::{export_gif, GifSettings},
+        png::export_png,
+        project::save_project_input,
+        sheet::{export_sheet, JsonFormat, SheetLayout, SheetSettings},
+        DocumentPath,
+    },
+    group::group_input,
+    history::{
+        history_panel_input, jump_history, setup_history_panel, update_history_panel, HistoryPanel,
+    },
+    layer::{flatten_input, layer_input, layer_z_order, link_input},
+    onion::{onion_skin_input, setup_onion_skin, update_onion_skin, OnionSkin},
+    resize::{resize_input, ResizeSettings},
+    selection::{
+        select_input, selection_input, setup_selection_outline, update_selection_outline,
+        wand_input, SelectState, Selection, WandSettings,
+    },
+    timeline::{
+        fit_frame_range, frame_input, playback, tag_input, timeline_input, timeline_layer_fix,
+        update_layer_textures, GlobalTimeline,
+    },
     tools::{
         brush::{brush_preview, painting, start_painting, stop_painting, BrushMode},
         bucket::{filling, start_filling, stop_filling},
-        BrushState, BucketState, Tool,
+        transform::{move_input, put_down_floating},
+        BrushState, BucketState, MoveState, Tool,
     },
-    undo_redo, ColorPalette, ColorState, History, ToolState, HEIGHT, WIDTH,
+    undo_redo, ColorPalette, ColorState, History, HistoryItem, ToolState,
 };
 
 fn main() {
+    let (path, settings, gif_settings, sheet_settings) = parse_args();
+
     App::new()
         .add_plugins(DefaultPlugins)
+        .insert_resource(path)
+        .insert_resource(settings)
+        .insert_resource(gif_settings)
+        .insert_resource(sheet_settings)
         .add_state::<ToolState>()
         .add_state::<Tool>()
         .init_resource::<BrushState>()
         .init_resource::<BucketState>()
+        .init_resource::<MoveState>()
         .init_resource::<History>()
+        .init_resource::<Selection>()
+        .init_resource::<SelectState>()
+        .init_resource::<WandSettings>()
+        .init_resource::<HistoryPanel>()
+        .init_resource::<ResizeSettings>()
+        .init_resource::<GlobalTimeline>()
+        .init_resource::<OnionSkin>()
+        .init_resource::<Autosave>()
         // .init_resource::<ColorPalette>()
         .insert_resource(ColorPalette {
             palette: vec![Color::WHITE, Color::PINK, Color::rgba(0.2, 0.1, 0.6, 0.5)],
             color_state: ColorState::default(),
         })
-        .add_systems(Startup, (setup_canvas, setup_camera, setup_background))
+        .add_systems(
+            Startup,
+            (
+                setup_canvas,
+                setup_camera,
+                setup_background,
+                setup_composite,
+                setup_onion_skin,
+                check_recovery,
+                setup_history_panel,
+                setup_selection_outline,
+            ),
+        )
         .add_systems(PreUpdate, cursor_position)
+        .add_systems(PostUpdate, (update_composite, update_onion_skin))
         .add_systems(
             Update,
             (
                 shadow_paralax,
+                shadow_texture,
+                resize_background,
+                new_document,
+                resize_input,
+                layer_input,
+                flatten_input,
+                group_input,
+                timeline_layer_fix,
+                layer_z_order,
                 undo_redo,
+                export_png,
+                export_gif,
+                save_project_input,
                 change_tool,
                 change_color,
+                palette_input,
                 move_camera,
                 zoom_camera,
             ),
         )
+        .add_systems(
+            Update,
+            (
+                frame_input,
+                tag_input,
+                fit_frame_range,
+                timeline_input,
+                playback,
+                update_layer_textures,
+            )
+                .chain(),
+        )
+        .add_systems(
+            Update,
+            (onion_skin_input, link_input, export_sheet, export_aseprite),
+        )
+        .add_systems(Update, (track_changes, autosave, recovery_input).chain())
+        .add_systems(
+            Update,
+            (history_panel_input, jump_history, update_history_panel).chain(),
+        )
+        .add_systems(Update, (selection_input, update_selection_outline).chain())
+        .add_systems(Last, remove_recovery_on_exit)
         .add_systems(
             Update,
             (
@@ -45,6 +143,12 @@ fn main() {
                 painting.run_if(in_state(ToolState::Painting)),
                 bucket_input.run_if(in_state(Tool::Bucket)),
                 filling.run_if(in_state(ToolState::Filling)),
+                select_input.run_if(in_state(Tool::Select)),
+                wand_input.run_if(in_state(Tool::Wand)),
+                // enter puts the moved pixels down instead of playing the animation
+                move_input
+                    .run_if(in_state(Tool::Move))
+                    .after(timeline_input),
                 // brush_preview.run_if(in_state(Tool::Brush).and_then(in_state(ToolState::Idle))),
             ),
         )
@@ -53,9 +157,97 @@ fn main() {
         .add_systems(OnExit(ToolState::Painting), stop_painting)
         .add_systems(OnEnter(ToolState::Filling), start_filling)
         .add_systems(OnExit(ToolState::Filling), stop_filling)
+        .add_systems(OnExit(Tool::Move), put_down_floating)
         .run();
 }
 
+/// `sprinkle [path] [--size WIDTHxHEIGHT] [--gif-scale N] [--gif-key RRGGBB|none]
+/// [--sheet-layout grid|horizontal|packed] [--sheet-padding N] [--sheet-extrude N] [--sheet-trim]
+/// [--sheet-json hash|array]`
+fn parse_args() -> (DocumentPath, DocumentSettings, GifSettings, SheetSettings) {
+    let mut path = DocumentPath::default();
+    let mut settings = DocumentSettings::default();
+    let mut gif_settings = GifSettings::default();
+    let mut sheet_settings = SheetSettings::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--gif-scale" => {
+                let scale = args.next();
+                match scale.as_deref().and_then(|scale| scale.parse().ok()) {
+                    Some(scale) if scale > 0 => gif_settings.scale = scale,
+                    _ => eprintln!("invalid gif scale {scale:?}, expected something like `4`"),
+                }
+                continue;
+            }
+            "--gif-key" => {
+                let key = args.next();
+                match key.as_deref().and_then(GifSettings::parse_key) {
+                    Some(key) => gif_settings.transparency_key = key,
+                    None => eprintln!(
+                        "invalid gif key {key:?}, expected something like `ff00ff` or `none`"
+                    ),
+                }
+                continue;
+            }
+            "--sheet-layout" => {
+                let layout = args.next();
+                match layout.as_deref().and_then(SheetLayout::parse) {
+                    Some(layout) => sheet_settings.layout = layout,
+                    None => eprintln!(
+                        "invalid sheet layout {layout:?}, expected `grid`, `horizontal` or `packed`"
+                    ),
+                }
+                continue;
+            }
+            "--sheet-padding" | "--sheet-extrude" => {
+                let value = args.next();
+                match value.as_deref().and_then(|value| value.parse().ok()) {
+                    Some(value) if arg == "--sheet-padding" => sheet_settings.padding = value,
+                    Some(value) => sheet_settings.extrude = value,
+                    None => eprintln!("invalid {arg} {value:?}, expected something like `1`"),
+                }
+                continue;
+            }
+            "--sheet-trim" => {
+                sheet_settings.trim = true;
+                continue;
+            }
+            "--sheet-json" => {
+                let format = args.next();
+                match format.as_deref().and_then(JsonFormat::parse) {
+                    Some(format) => sheet_settings.json = format,
+                    None => eprintln!("invalid json format {format:?}, expected `hash` or `array`"),
+                }
+                continue;
+            }
+            _ => (),
+        }
+
+        let size = match arg.as_str() {
+            "--size" | "-s" => args.next(),
+            _ => match arg.strip_prefix("--size=") {
+                Some(size) => Some(size.to_string()),
+                None => {
+                    path.0 = PathBuf::from(&arg);
+                    continue;
+                }
+            },
+        };
+
+        match size.as_deref().and_then(DocumentSettings::parse_size) {
+            Some((width, height)) => {
+                settings.width = width;
+                settings.height = height;
+            }
+            None => eprintln!("invalid size {size:?}, expected something like `32x32`"),
+        }
+    }
+
+    (path, settings, gif_settings, sheet_settings)
+}
+
 #[derive(Component)]
 pub struct Background;
 
@@ -67,7 +259,8 @@ fn setup_background(mut commands: Commands) {
                 ..Default::default()
             },
             transform: Transform {
-                scale: Vec3::new(WIDTH as f32, HEIGHT as f32, 1.0),
+                // resized by `resize_background`
+                scale: Vec3::ONE,
                 translation: Vec3::new(0.0, 0.0, 0.0),
                 ..Default::default()
             },
@@ -77,6 +270,22 @@ fn setup_background(mut commands: Commands) {
     ));
 }
 
+fn resize_background(
+    canvas: Res<Canvas>,
+    mut background: Query<&mut Transform, With<Background>>,
+    mut last_size: Local<(u32, u32)>,
+) {
+    // the canvas changes every frame because of the cursor, only its size matters here
+    let size = (canvas.width, canvas.height);
+    if size == *last_size {
+        return;
+    }
+    *last_size = size;
+    for mut trans in &mut background {
+        trans.scale = canvas.size().extend(1.0);
+    }
+}
+
 fn change_color(input: Res<Input<KeyCode>>, mut palette: ResMut<ColorPalette>) {
     if input.just_pressed(KeyCode::Key1) {
         palette.set_primary(0);
@@ -89,7 +298,64 @@ fn change_color(input: Res<Input<KeyCode>>, mut palette: ResMut<ColorPalette>) {
     }
 }
 
+/// [k] : add the main color to the palette
+/// [shift + k] : remove the main color from the palette
+fn palette_input(
+    input: Res<Input<KeyCode>>,
+    mut palette: ResMut<ColorPalette>,
+    mut history: ResMut<History>,
+) {
+    if !input.just_pressed(KeyCode::K) || input.pressed(KeyCode::LControl) {
+        return;
+    }
+    let before = palette.clone();
+    let color = palette.primary_color();
+    let index = palette.palette.iter().position(|c| *c == color);
+
+    if input.pressed(KeyCode::LShift) {
+        let Some(index) = index else {
+            warn!("the main color isn't in the palette");
+            return;
+        };
+        if palette.palette.len() == 1 {
+            warn!("the palette needs at least one color");
+            return;
+        }
+        palette.palette.remove(index);
+        history.add(
+            format!("Remove color {} from the palette", index),
+            HistoryItem::Palette(before),
+        );
+        let last = palette.palette.len() as u8 - 1;
+        if let ColorState::Indexed { primary, secondary } = &mut palette.color_state {
+            for picked in [primary, secondary] {
+                if *picked > index as u8 {
+                    *picked -= 1;
+                }
+                *picked = (*picked).min(last);
+            }
+        }
+        info!("removed color {} from the palette", index);
+    } else {
+        if index.is_some() {
+            warn!("the main color is already in the palette");
+            return;
+        }
+        palette.palette.push(color);
+        let index = palette.palette.len() - 1;
+        history.add(
+            format!("Add color {} to the palette", index),
+            HistoryItem::Palette(before),
+        );
+        info!("added color {} to the palette", index);
+    }
+}
+
 fn change_tool(input: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<Tool>>) {
+    // ctrl + g groups layers and ctrl + r restores a recovery file
+    if input.pressed(KeyCode::LControl) {
+        return;
+    }
     if input.just_pressed(KeyCode::B) {
         next_state.set(Tool::Brush);
         info!("[TOOL] : Brush");
@@ -102,6 +368,23 @@ fn change_tool(input: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<Tool
         next_state.set(Tool::Select);
         info!("[TOOL] : Select");
     }
+    if input.just_pressed(KeyCode::W) {
+        next_state.set(Tool::Wand);
+        info!("[TOOL] : Wand");
+    }
+    if input.just_pressed(KeyCode::V) {
+        next_state.set(Tool::Move);
+        info!("[TOOL] : Move");
+    }
+}
+
+/// warn if the active layer can't be drawn on.
+fn active_layer_editable(canvas: &Canvas, tree: &LayerTree) -> bool {
+    let editable = tree.is_editable(canvas.layer_id);
+    if !editable {
+        warn!("the active layer is hidden or locked");
+    }
+    editable
 }
 
 fn brush_input(
@@ -109,7 +392,15 @@ fn brush_input(
     mut next_state: ResMut<NextState<ToolState>>,
     color: Res<ColorPalette>,
     mut brush: ResMut<BrushState>,
+    canvas: Res<Canvas>,
+    tree: LayerTree,
 ) {
+    if mouse.any_just_pressed([MouseButton::Left, MouseButton::Right])
+        && !active_layer_editable(&canvas, &tree)
+    {
+        return;
+    }
+
     if mouse.just_pressed(MouseButton::Left) {
         brush.color = color.primary_color();
         next_state.set(ToolState::Painting);
@@ -132,7 +423,15 @@ fn bucket_input(
     mut next_state: ResMut<NextState<ToolState>>,
     color: Res<ColorPalette>,
     mut bucket: ResMut<BucketState>,
+    canvas: Res<Canvas>,
+    tree: LayerTree,
 ) {
+    if input.any_just_pressed([MouseButton::Left, MouseButton::Right])
+        && !active_layer_editable(&canvas, &tree)
+    {
+        return;
+    }
+
     if input.just_pressed(MouseButton::Left) {
         bucket.fill_color = color.primary_color();
         next_state.set(ToolState::Filling);
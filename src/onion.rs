@@ -0,0 +1,292 @@
+use bevy::prelude::*;
+
+use crate::{
+    canvas::Canvas,
+    composite::{blend, BlendMode},
+    group::Detached,
+    layer::Layer,
+    timeline::{GlobalTimeline, Timeline},
+};
+
+/// which frames are shown around the current one.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnionRange {
+    /// stay inside the tag of the current frame
+    Tag,
+    /// wrap around the frame range of the [`GlobalTimeline`]
+    #[default]
+    Loop,
+}
+
+#[derive(Resource)]
+pub struct OnionSkin {
+    pub enabled: bool,
+    /// how many frames before the current one are shown
+    pub previous: u32,
+    /// how many frames after the current one are shown
+    pub next: u32,
+    /// the opacity of the closest frames, the ones further away fade out
+    pub opacity: f32,
+    /// tint previous frames red and next frames blue
+    pub tint: bool,
+    pub range: OnionRange,
+    /// only show the active layer, otherwise every visible layer with
+    /// [`Layer::onion_skin`] is shown
+    pub active_layer_only: bool,
+}
+
+impl Default for OnionSkin {
+    fn default() -> Self {
+        OnionSkin {
+            enabled: false,
+            previous: 1,
+            next: 1,
+            opacity: 0.4,
+            tint: false,
+            range: OnionRange::Loop,
+            active_layer_only: true,
+        }
+    }
+}
+
+impl OnionSkin {
+    /// the frames to show around `frame` and their opacity, furthest first.
+    ///
+    /// `(first, last)` are the frames the onion skin can reach.
+    pub fn frames(&self, frame: i32, first: i32, last: i32, wrap: bool) -> Vec<(i32, f32, bool)> {
+        let len = last - first + 1;
+        let fade = |i: u32, count: u32| self.opacity * (count - i + 1) as f32 / count as f32;
+
+        let mut frames = Vec::new();
+        for (count, sign, is_previous) in [(self.previous, -1, true), (self.next, 1, false)] {
+            for i in (1..=count).rev() {
+                let mut other = frame + sign * i as i32;
+                if wrap {
+                    other = first + (other - first).rem_euclid(len);
+                }
+                if other == frame || !(first..=last).contains(&other) {
+                    continue;
+                }
+                frames.push((other, fade(i, count), is_previous));
+            }
+        }
+        frames
+    }
+}
+
+/// tint `data` towards red or blue, keeping the alpha.
+fn tint(data: &mut [u8], color: [u8; 3]) {
+    for pixel in data.array_chunks_mut::<4>() {
+        for (c, t) in pixel.iter_mut().zip(color) {
+            *c = ((*c as u16 + t as u16) / 2) as u8;
+        }
+    }
+}
+
+/// the sprite showing the frames around the current one, behind the composite.
+#[derive(Component)]
+pub struct Onion;
+
+pub fn setup_onion_skin(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let image = images.add(crate::image(1, 1, Color::NONE));
+
+    commands.spawn((
+        SpriteBundle {
+            texture: image,
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.5)),
+            visibility: Visibility::Hidden,
+            ..Default::default()
+        },
+        Onion,
+    ));
+}
+
+/// redraw the onion skin when the current frame, the settings or the layers changed.
+///
+/// it's hidden while the animation is playing.
+pub fn update_onion_skin(
+    canvas: Res<Canvas>,
+    settings: Res<OnionSkin>,
+    global_timeline: Res<GlobalTimeline>,
+    layers: Query<(Entity, &Layer), Without<Detached>>,
+    timelines: Query<&Timeline>,
+    changed: Query<(), Or<(Changed<Layer>, Changed<Timeline>)>>,
+    mut events: EventReader<AssetEvent<Image>>,
+    mut onion: Query<(&Handle<Image>, &mut Visibility), With<Onion>>,
+    mut images: ResMut<Assets<Image>>,
+    mut last: Local<(i32, Option<Entity>, UVec2)>,
+) {
+    let (handle, mut visibility) = onion.single_mut();
+
+    let show = settings.enabled && !global_timeline.is_playing();
+    let new_visibility = if show {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+    if *visibility != new_visibility {
+        *visibility = new_visibility;
+    }
+    if !show {
+        return;
+    }
+
+    let current = (
+        global_timeline.current_frame(),
+        Some(canvas.layer_id),
+        UVec2::new(canvas.width, canvas.height),
+    );
+    let mut dirty =
+        *last != current || settings.is_changed() || visibility.is_changed() || !changed.is_empty();
+    *last = current;
+    for ev in events.iter() {
+        if let AssetEvent::Modified { handle: modified } = ev {
+            dirty |= modified != handle
+                && layers
+                    .iter()
+                    .any(|(_, layer)| layer.frames.values().any(|h| h == modified));
+        }
+    }
+    if !dirty {
+        return;
+    }
+
+    let Ok((_, active)) = layers.get(canvas.layer_id) else {
+        return;
+    };
+    let frame = global_timeline.current_frame();
+    let range = global_timeline.frame_range();
+    let (first, last, wrap) = match settings.range {
+        OnionRange::Loop => (range.start as i32, range.end as i32 - 1, true),
+        OnionRange::Tag => {
+            // the tag of the current frame on the active timeline, or everything
+            let tag = active
+                .timeline_id
+                .and_then(|id| timelines.get(id).ok())
+                .and_then(|timeline| {
+                    let local = timeline.local_frame(frame);
+                    let tag = &timeline.tags()[timeline.tag_at(local)?];
+                    let offset = timeline.frame_offset();
+                    Some((tag.from + offset, tag.to + offset))
+                });
+            match tag {
+                Some((from, to)) => (from, to, false),
+                None => (range.start as i32, range.end as i32 - 1, false),
+            }
+        }
+    };
+
+    let shown = layers
+        .iter()
+        .filter(|(id, layer)| {
+            if settings.active_layer_only {
+                *id == canvas.layer_id && layer.onion_skin
+            } else {
+                layer.visible && layer.onion_skin
+            }
+        })
+        .filter_map(|(_, layer)| Some((layer, timelines.get(layer.timeline_id?).ok()?)))
+        .collect::<Vec<_>>();
+
+    let len = (canvas.width * canvas.height * 4) as usize;
+    let mut data = vec![0; len];
+    for (other, opacity, is_previous) in settings.frames(frame, first, last, wrap) {
+        let mut frame_data = vec![0; len];
+        for (layer, timeline) in &shown {
+            let Some(image) = layer
+                .frames
+                .get(&timeline.local_frame(other))
+                .and_then(|handle| images.get(handle))
+            else {
+                continue;
+            };
+            blend(
+                &mut frame_data,
+                &image.data,
+                layer.opacity,
+                layer.blend_mode,
+            );
+        }
+        if settings.tint {
+            tint(
+                &mut frame_data,
+                if is_previous {
+                    [255, 0, 0]
+                } else {
+                    [0, 0, 255]
+                },
+            );
+        }
+        blend(&mut data, &frame_data, opacity, BlendMode::Normal);
+    }
+
+    let image = images.get_mut(handle).unwrap();
+    image.data = data;
+    crate::resize::set_image_size(image, canvas.width, canvas.height);
+}
+
+/// [o] : show / hide the onion skin
+/// [shift + o] : tint previous frames red and next frames blue
+/// [alt + o] : keep the onion skin inside the tag or loop around the frame range
+/// [ctrl + o] : include / exclude the active layer from the onion skin
+/// [ctrl + shift + o] : show the onion skin of every layer or only the active one
+/// [bracket left / right] : show fewer / more frames
+pub fn onion_skin_input(
+    input: Res<Input<KeyCode>>,
+    canvas: Res<Canvas>,
+    mut settings: ResMut<OnionSkin>,
+    mut layers: Query<&mut Layer>,
+) {
+    let ctrl = input.pressed(KeyCode::LControl);
+    let shift = input.pressed(KeyCode::LShift);
+    let alt = input.pressed(KeyCode::LAlt);
+
+    if input.just_pressed(KeyCode::O) {
+        match (ctrl, shift, alt) {
+            (false, false, false) => {
+                settings.enabled = !settings.enabled;
+                info!("onion skin: {}", settings.enabled);
+            }
+            (false, true, false) => {
+                settings.tint = !settings.tint;
+                info!("onion skin tint: {}", settings.tint);
+            }
+            (false, false, true) => {
+                settings.range = match settings.range {
+                    OnionRange::Tag => OnionRange::Loop,
+                    OnionRange::Loop => OnionRange::Tag,
+                };
+                info!("onion skin range: {:?}", settings.range);
+            }
+            (true, false, false) => {
+                if let Ok(mut layer) = layers.get_mut(canvas.layer_id) {
+                    layer.onion_skin = !layer.onion_skin;
+                    info!("{} onion skin: {}", layer.name, layer.onion_skin);
+                }
+            }
+            (true, true, false) => {
+                settings.active_layer_only = !settings.active_layer_only;
+                info!(
+                    "onion skin active layer only: {}",
+                    settings.active_layer_only
+                );
+            }
+            _ => (),
+        }
+    }
+
+    // alt + brackets change the tolerance of the magic wand
+    if input.pressed(KeyCode::LAlt) {
+        return;
+    }
+    if input.just_pressed(KeyCode::BracketLeft) {
+        settings.previous = settings.previous.saturating_sub(1);
+        settings.next = settings.next.saturating_sub(1);
+        info!("onion skin frames: {}", settings.previous);
+    }
+    if input.just_pressed(KeyCode::BracketRight) {
+        settings.previous += 1;
+        settings.next += 1;
+        info!("onion skin frames: {}", settings.previous);
+    }
+}
@@ -0,0 +1,442 @@
+use bevy::{prelude::*, render::render_resource::Extent3d, utils::HashSet};
+
+use crate::{
+    canvas::Canvas, layer::Layer, selection::Selection, tools::MoveState, History, HistoryItem,
+};
+
+/// which part of the image stays in place when the canvas is resized.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// 0, 1 or 2 on each axis, counted from the top left
+    fn factor(self) -> IVec2 {
+        match self {
+            Anchor::TopLeft => IVec2::new(0, 0),
+            Anchor::Top => IVec2::new(1, 0),
+            Anchor::TopRight => IVec2::new(2, 0),
+            Anchor::Left => IVec2::new(0, 1),
+            Anchor::Center => IVec2::new(1, 1),
+            Anchor::Right => IVec2::new(2, 1),
+            Anchor::BottomLeft => IVec2::new(0, 2),
+            Anchor::Bottom => IVec2::new(1, 2),
+            Anchor::BottomRight => IVec2::new(2, 2),
+        }
+    }
+
+    /// where the top left corner of an `old` sized image ends up in a `new` sized one.
+    pub fn offset(self, old: UVec2, new: UVec2) -> IVec2 {
+        (new.as_ivec2() - old.as_ivec2()) * self.factor() / 2
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ResizeSettings {
+    pub anchor: Anchor,
+}
+
+pub fn set_image_size(image: &mut Image, width: u32, height: u32) {
+    image.texture_descriptor.size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+}
+
+/// copy `data` into a new transparent buffer of size `new`, with its top left corner at `offset`.
+///
+/// pixels that end up outside the new buffer are dropped.
+pub fn reframe(data: &[u8], old: UVec2, new: UVec2, offset: IVec2) -> Vec<u8> {
+    let mut out = vec![0; (new.x * new.y * 4) as usize];
+
+    // the overlapping columns are the same for every row
+    let x0 = 0.max(-offset.x);
+    let x1 = (old.x as i32).min(new.x as i32 - offset.x);
+    if x0 >= x1 {
+        return out;
+    }
+    let len = ((x1 - x0) * 4) as usize;
+
+    for y in 0..old.y as i32 {
+        let new_y = y + offset.y;
+        if new_y < 0 || new_y >= new.y as i32 {
+            continue;
+        }
+        let src = ((y * old.x as i32 + x0) * 4) as usize;
+        let dst = ((new_y * new.x as i32 + x0 + offset.x) * 4) as usize;
+        out[dst..dst + len].copy_from_slice(&data[src..src + len]);
+    }
+    out
+}
+
+/// the smallest rectangle `(min, max)` (inclusive) containing every pixel that isn't fully transparent.
+pub fn opaque_bounds(data: &[u8], width: u32) -> Option<(UVec2, UVec2)> {
+    let mut bounds: Option<(UVec2, UVec2)> = None;
+
+    for (i, [.., a]) in data.array_chunks::<4>().enumerate() {
+        if *a == 0 {
+            continue;
+        }
+        let pos = UVec2::new(i as u32 % width, i as u32 / width);
+        bounds = Some(match bounds {
+            Some((min, max)) => (min.min(pos), max.max(pos)),
+            None => (pos, pos),
+        });
+    }
+    bounds
+}
+
+/// move every frame of every layer into a canvas of `size`, with the old top left corner at `offset`.
+///
+/// returns the history item that reverts it.
+pub fn reframe_canvas(
+    canvas: &mut Canvas,
+    layers: &Query<(Entity, &Layer)>,
+    images: &mut Assets<Image>,
+    size: UVec2,
+    offset: IVec2,
+) -> HistoryItem {
+    let old = UVec2::new(canvas.width, canvas.height);
+
+    // frames can share an image, make sure every image is only touched once
+    let mut visited = HashSet::new();
+    let mut frames = Vec::new();
+
+    for (layer_id, layer) in layers {
+        for (frame, handle) in &layer.frames {
+            if !visited.insert(handle.id()) {
+                continue;
+            }
+            let Some(image) = images.get_mut(handle) else {
+                continue;
+            };
+            let data = reframe(&image.data, old, size, offset);
+            set_image_size(image, size.x, size.y);
+            frames.push((layer_id, *frame, std::mem::replace(&mut image.data, data)));
+        }
+    }
+
+    canvas.width = size.x;
+    canvas.height = size.y;
+
+    HistoryItem::Resized {
+        width: old.x,
+        height: old.y,
+        frames,
+    }
+}
+
+pub fn resize_canvas(
+    canvas: &mut Canvas,
+    layers: &Query<(Entity, &Layer)>,
+    images: &mut Assets<Image>,
+    size: UVec2,
+    anchor: Anchor,
+) -> HistoryItem {
+    let offset = anchor.offset(UVec2::new(canvas.width, canvas.height), size);
+    reframe_canvas(canvas, layers, images, size, offset)
+}
+
+/// crop the canvas to the rectangle starting at `min` with the given `size`.
+pub fn crop_canvas(
+    canvas: &mut Canvas,
+    layers: &Query<(Entity, &Layer)>,
+    images: &mut Assets<Image>,
+    min: UVec2,
+    size: UVec2,
+) -> HistoryItem {
+    reframe_canvas(canvas, layers, images, size, -min.as_ivec2())
+}
+
+/// crop away the transparent borders shared by every frame of every layer.
+///
+/// returns `None` if the whole canvas is transparent.
+pub fn trim_canvas(
+    canvas: &mut Canvas,
+    layers: &Query<(Entity, &Layer)>,
+    images: &mut Assets<Image>,
+) -> Option<HistoryItem> {
+    let (min, max) = layers
+        .iter()
+        .flat_map(|(_, layer)| layer.frames.values())
+        .filter_map(|handle| images.get(handle))
+        .filter_map(|image| opaque_bounds(&image.data, canvas.width))
+        .reduce(|(min0, max0), (min1, max1)| (min0.min(min1), max0.max(max1)))?;
+
+    let size = max - min + UVec2::ONE;
+    if size == UVec2::new(canvas.width, canvas.height) {
+        return None;
+    }
+    Some(crop_canvas(canvas, layers, images, min, size))
+}
+
+/// the selection doesn't fit the new canvas, it's cleared and put back on undo.
+fn with_selection(item: HistoryItem, selection: &mut Selection) -> HistoryItem {
+    if selection.is_empty() {
+        return item;
+    }
+    let mask = std::mem::take(&mut selection.mask);
+    HistoryItem::Compound(
+        String::from("resize"),
+        vec![item, HistoryItem::Selected(mask)],
+    )
+}
+
+/// [alt + numpad] : choose the resize anchor
+/// [alt + arrow keys] : grow or shrink the canvas by one pixel around the anchor
+/// [ctrl + t] : trim transparent borders
+/// [ctrl + shift + t] : crop the canvas to the selection
+pub fn resize_input(
+    input: Res<Input<KeyCode>>,
+    mut settings: ResMut<ResizeSettings>,
+    mut canvas: ResMut<Canvas>,
+    layers: Query<(Entity, &Layer)>,
+    mut images: ResMut<Assets<Image>>,
+    mut history: ResMut<History>,
+    mut selection: ResMut<Selection>,
+    move_state: Res<MoveState>,
+) {
+    // the pixels held by the move tool are as big as the canvas they were lifted from
+    let trim = input.pressed(KeyCode::LControl) && input.just_pressed(KeyCode::T);
+    let resize = input.pressed(KeyCode::LAlt)
+        && input.any_just_pressed([KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down]);
+    if move_state.is_floating() && (trim || resize) {
+        warn!("put the moved pixels down before resizing the canvas");
+        return;
+    }
+
+    if trim && input.pressed(KeyCode::LShift) {
+        let Some((min, max)) = selection.bounds(canvas.width) else {
+            warn!("nothing is selected");
+            return;
+        };
+        let size = max - min + UVec2::ONE;
+        if size == UVec2::new(canvas.width, canvas.height) {
+            return;
+        }
+        let item = crop_canvas(&mut canvas, &layers, &mut images, min, size);
+        info!("cropped canvas to {}x{}", canvas.width, canvas.height);
+        history.add(
+            format!("Crop canvas to {}x{}", canvas.width, canvas.height),
+            with_selection(item, &mut selection),
+        );
+        return;
+    }
+    if trim {
+        if let Some(item) = trim_canvas(&mut canvas, &layers, &mut images) {
+            info!("trimmed canvas to {}x{}", canvas.width, canvas.height);
+            history.add(
+                format!("Trim canvas to {}x{}", canvas.width, canvas.height),
+                with_selection(item, &mut selection),
+            );
+        }
+        return;
+    }
+
+    if !input.pressed(KeyCode::LAlt) {
+        return;
+    }
+
+    for (key, anchor) in [
+        (KeyCode::Numpad7, Anchor::TopLeft),
+        (KeyCode::Numpad8, Anchor::Top),
+        (KeyCode::Numpad9, Anchor::TopRight),
+        (KeyCode::Numpad4, Anchor::Left),
+        (KeyCode::Numpad5, Anchor::Center),
+        (KeyCode::Numpad6, Anchor::Right),
+        (KeyCode::Numpad1, Anchor::BottomLeft),
+        (KeyCode::Numpad2, Anchor::Bottom),
+        (KeyCode::Numpad3, Anchor::BottomRight),
+    ] {
+        if input.just_pressed(key) {
+            settings.anchor = anchor;
+            info!("resize anchor: {:?}", anchor);
+        }
+    }
+
+    let mut delta = IVec2::ZERO;
+    if input.just_pressed(KeyCode::Right) {
+        delta.x += 1;
+    }
+    if input.just_pressed(KeyCode::Left) {
+        delta.x -= 1;
+    }
+    if input.just_pressed(KeyCode::Up) {
+        delta.y += 1;
+    }
+    if input.just_pressed(KeyCode::Down) {
+        delta.y -= 1;
+    }
+    if delta == IVec2::ZERO {
+        return;
+    }
+
+    let size = (IVec2::new(canvas.width as i32, canvas.height as i32) + delta)
+        .max(IVec2::ONE)
+        .as_uvec2();
+    let item = resize_canvas(&mut canvas, &layers, &mut images, size, settings.anchor);
+    info!("resized canvas to {}x{}", canvas.width, canvas.height);
+    history.add(
+        format!("Resize canvas to {}x{}", canvas.width, canvas.height),
+        with_selection(item, &mut selection),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+
+    const CLEAR: [u8; 4] = [0; 4];
+    const RED: [u8; 4] = [200, 0, 0, 255];
+
+    /// a `width` x `height` image whose pixels are numbered, so moved pixels can be told apart.
+    fn numbered(width: u32, height: u32) -> Vec<u8> {
+        (1..=width * height)
+            .flat_map(|i| [i as u8, 0, 0, 255])
+            .collect()
+    }
+
+    fn pixel(data: &[u8], width: u32, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * width + x) * 4) as usize;
+        data[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn shrinking_moves_the_corner_out_of_the_image() {
+        let (old, new) = (UVec2::new(4, 4), UVec2::new(2, 2));
+        assert_eq!(Anchor::TopLeft.offset(old, new), IVec2::ZERO);
+        assert_eq!(Anchor::Center.offset(old, new), IVec2::new(-1, -1));
+        assert_eq!(Anchor::BottomRight.offset(old, new), IVec2::new(-2, -2));
+        assert_eq!(Anchor::Right.offset(old, new), IVec2::new(-2, -1));
+    }
+
+    #[test]
+    fn odd_deltas_around_the_center_round_towards_the_top_left() {
+        // growing by 3 puts 1 pixel before the image and 2 after it
+        assert_eq!(
+            Anchor::Center.offset(UVec2::new(2, 2), UVec2::new(5, 3)),
+            IVec2::new(1, 0)
+        );
+        // and shrinking back by 3 takes the same pixels away
+        assert_eq!(
+            Anchor::Center.offset(UVec2::new(5, 3), UVec2::new(2, 2)),
+            IVec2::new(-1, 0)
+        );
+    }
+
+    #[test]
+    fn reframe_keeps_the_pixels_around_the_anchor() {
+        let (old, new) = (UVec2::new(4, 4), UVec2::new(2, 2));
+        let data = numbered(4, 4);
+
+        let out = reframe(&data, old, new, Anchor::Center.offset(old, new));
+        assert_eq!(out.len(), 2 * 2 * 4);
+        assert_eq!(pixel(&out, 2, 0, 0), pixel(&data, 4, 1, 1));
+        assert_eq!(pixel(&out, 2, 1, 0), pixel(&data, 4, 2, 1));
+        assert_eq!(pixel(&out, 2, 0, 1), pixel(&data, 4, 1, 2));
+        assert_eq!(pixel(&out, 2, 1, 1), pixel(&data, 4, 2, 2));
+
+        let out = reframe(&data, old, new, Anchor::BottomRight.offset(old, new));
+        assert_eq!(pixel(&out, 2, 0, 0), pixel(&data, 4, 2, 2));
+        assert_eq!(pixel(&out, 2, 1, 1), pixel(&data, 4, 3, 3));
+    }
+
+    #[test]
+    fn reframe_with_an_odd_center_delta_round_trips() {
+        let (old, new) = (UVec2::new(2, 2), UVec2::new(5, 5));
+        let data = numbered(2, 2);
+
+        let grown = reframe(&data, old, new, Anchor::Center.offset(old, new));
+        assert_eq!(pixel(&grown, 5, 0, 0), CLEAR);
+        assert_eq!(pixel(&grown, 5, 1, 1), pixel(&data, 2, 0, 0));
+        assert_eq!(pixel(&grown, 5, 2, 2), pixel(&data, 2, 1, 1));
+        assert_eq!(pixel(&grown, 5, 3, 3), CLEAR);
+
+        let shrunk = reframe(&grown, new, old, Anchor::Center.offset(new, old));
+        assert_eq!(shrunk, data);
+    }
+
+    #[test]
+    fn reframe_past_the_edge_is_transparent() {
+        let data = numbered(2, 2);
+        let out = reframe(&data, UVec2::new(2, 2), UVec2::new(2, 2), IVec2::new(-2, 0));
+        assert_eq!(out, vec![0; 2 * 2 * 4]);
+        let out = reframe(&data, UVec2::new(2, 2), UVec2::new(2, 2), IVec2::new(0, 3));
+        assert_eq!(out, vec![0; 2 * 2 * 4]);
+    }
+
+    #[test]
+    fn opaque_bounds_ignore_transparent_pixels() {
+        assert_eq!(opaque_bounds(&[0; 3 * 3 * 4], 3), None);
+
+        let mut data = vec![0; 3 * 3 * 4];
+        // only the alpha counts
+        data[..3].copy_from_slice(&[255, 255, 255]);
+        data[(3 + 1) * 4..(3 + 2) * 4].copy_from_slice(&RED);
+        data[(2 * 3 + 2) * 4..(2 * 3 + 3) * 4].copy_from_slice(&RED);
+        assert_eq!(
+            opaque_bounds(&data, 3),
+            Some((UVec2::new(1, 1), UVec2::new(2, 2)))
+        );
+    }
+
+    /// trim a `width` x `height` canvas with a layer for each image.
+    fn trim(width: u32, height: u32, frames: Vec<Vec<u8>>) -> (Canvas, Option<HistoryItem>) {
+        let mut app = App::new();
+        app.add_plugins(AssetPlugin::default()).add_asset::<Image>();
+        for data in frames {
+            let mut image = crate::image(width, height, Color::NONE);
+            image.data = data;
+            let handle = app.world.resource_mut::<Assets<Image>>().add(image);
+            app.world.spawn(Layer::new("layer", handle, None));
+        }
+
+        let mut canvas = Canvas::new(width, height, Entity::PLACEHOLDER);
+        let mut state =
+            SystemState::<(Query<(Entity, &Layer)>, ResMut<Assets<Image>>)>::new(&mut app.world);
+        let (layers, mut images) = state.get_mut(&mut app.world);
+        let item = trim_canvas(&mut canvas, &layers, &mut images);
+        (canvas, item)
+    }
+
+    #[test]
+    fn trimming_a_transparent_canvas_does_nothing() {
+        let (canvas, item) = trim(3, 2, vec![vec![0; 3 * 2 * 4]]);
+        assert!(item.is_none());
+        assert_eq!((canvas.width, canvas.height), (3, 2));
+    }
+
+    #[test]
+    fn trimming_keeps_the_pixels_of_every_layer() {
+        let mut bottom = vec![0; 4 * 3 * 4];
+        bottom[(4 + 1) * 4..(4 + 2) * 4].copy_from_slice(&RED);
+        let mut top = vec![0; 4 * 3 * 4];
+        top[(2 * 4 + 2) * 4..(2 * 4 + 3) * 4].copy_from_slice(&RED);
+
+        let (canvas, item) = trim(4, 3, vec![bottom, top]);
+        assert_eq!((canvas.width, canvas.height), (2, 2));
+        let Some(HistoryItem::Resized {
+            width,
+            height,
+            frames,
+        }) = item
+        else {
+            panic!("trimming should be undoable");
+        };
+        assert_eq!((width, height), (4, 3));
+        assert_eq!(frames.len(), 2);
+    }
+}
@@ -0,0 +1,752 @@
+use bevy::prelude::*;
+
+use crate::{
+    canvas::Canvas, composite::LayerTree, in_img_bounds, tools::bucket::flood_fill, History,
+    HistoryItem,
+};
+
+/// the pixels of the canvas tools are limited to, one byte per pixel (0 or 255).
+///
+/// an empty mask means nothing is selected, so everything can be edited.
+#[derive(Resource, Default, Clone)]
+pub struct Selection {
+    pub mask: Vec<u8>,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        self.mask.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.mask.clear();
+    }
+
+    /// whether the pixel at `pos` can be edited, every pixel can if nothing is selected.
+    pub fn contains(&self, pos: IVec2, width: u32) -> bool {
+        if self.mask.is_empty() {
+            return true;
+        }
+        if pos.x < 0 || pos.y < 0 || pos.x >= width as i32 {
+            return false;
+        }
+        self.contains_index((pos.y * width as i32 + pos.x) as usize)
+    }
+
+    /// the top left and bottom right selected pixels, `None` if nothing is selected.
+    pub fn bounds(&self, width: u32) -> Option<(UVec2, UVec2)> {
+        let mut bounds: Option<(UVec2, UVec2)> = None;
+        for (i, _) in self.mask.iter().enumerate().filter(|(_, m)| **m > 0) {
+            let pos = UVec2::new(i as u32 % width, i as u32 / width);
+            bounds = Some(match bounds {
+                Some((min, max)) => (min.min(pos), max.max(pos)),
+                None => (pos, pos),
+            });
+        }
+        bounds
+    }
+
+    /// like [`contains`](Self::contains) for the `i`th pixel.
+    pub fn contains_index(&self, i: usize) -> bool {
+        self.mask.is_empty() || self.mask.get(i).map_or(false, |m| *m > 0)
+    }
+
+    /// combine `mask` with the current selection, a mask with nothing in it is no selection.
+    pub fn combine(&mut self, mask: Vec<u8>, mode: SelectMode) {
+        match mode {
+            SelectMode::Replace => self.mask = mask,
+            SelectMode::Add if self.mask.is_empty() => self.mask = mask,
+            // there's nothing to take away from
+            SelectMode::Subtract | SelectMode::Intersect if self.mask.is_empty() => (),
+            SelectMode::Add => {
+                for (m, other) in self.mask.iter_mut().zip(mask) {
+                    *m = (*m).max(other);
+                }
+            }
+            SelectMode::Subtract => {
+                for (m, other) in self.mask.iter_mut().zip(mask) {
+                    if other > 0 {
+                        *m = 0;
+                    }
+                }
+            }
+            SelectMode::Intersect => {
+                for (m, other) in self.mask.iter_mut().zip(mask) {
+                    *m = (*m).min(other);
+                }
+            }
+        }
+        self.clear_if_empty();
+    }
+
+    fn clear_if_empty(&mut self) {
+        if self.mask.iter().all(|m| *m == 0) {
+            self.mask.clear();
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectShape {
+    #[default]
+    Rectangle,
+    Ellipse,
+    /// drawn freehand while the mouse is held
+    Lasso,
+    /// one corner per click
+    Polygon,
+}
+
+impl SelectShape {
+    pub fn next(self) -> Self {
+        match self {
+            SelectShape::Rectangle => SelectShape::Ellipse,
+            SelectShape::Ellipse => SelectShape::Lasso,
+            SelectShape::Lasso => SelectShape::Polygon,
+            SelectShape::Polygon => SelectShape::Rectangle,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SelectShape::Rectangle => "rectangle",
+            SelectShape::Ellipse => "ellipse",
+            SelectShape::Lasso => "lasso",
+            SelectShape::Polygon => "polygon",
+        }
+    }
+
+    /// the pixels inside the shape through `points`.
+    pub fn mask(self, points: &[IVec2], width: u32, height: u32) -> Vec<u8> {
+        match (self, points) {
+            (_, []) => vec![0; (width * height) as usize],
+            (SelectShape::Rectangle, [a, ..]) => {
+                rect_mask(*a, *points.last().unwrap(), width, height)
+            }
+            (SelectShape::Ellipse, [a, ..]) => {
+                ellipse_mask(*a, *points.last().unwrap(), width, height)
+            }
+            (SelectShape::Lasso | SelectShape::Polygon, _) => polygon_mask(points, width, height),
+        }
+    }
+}
+
+/// how a new shape is combined with the current selection.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectMode {
+    #[default]
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+}
+
+impl SelectMode {
+    /// shift adds, alt subtracts and both intersect.
+    pub fn from_input(input: &Input<KeyCode>) -> Self {
+        match (input.pressed(KeyCode::LShift), input.pressed(KeyCode::LAlt)) {
+            (true, true) => SelectMode::Intersect,
+            (true, false) => SelectMode::Add,
+            (false, true) => SelectMode::Subtract,
+            (false, false) => SelectMode::Replace,
+        }
+    }
+
+    /// like "Add ellipse to selection".
+    pub fn label(self, what: &str) -> String {
+        match self {
+            SelectMode::Replace => format!("Select {what}"),
+            SelectMode::Add => format!("Add {what} to selection"),
+            SelectMode::Subtract => format!("Subtract {what} from selection"),
+            SelectMode::Intersect => format!("Intersect selection with {what}"),
+        }
+    }
+}
+
+/// the shape being drawn with the select tool.
+#[derive(Resource, Default)]
+pub struct SelectState {
+    pub shape: SelectShape,
+    pub mode: SelectMode,
+    /// the corners of a rectangle or ellipse, or the path of a lasso or polygon
+    pub points: Vec<IVec2>,
+}
+
+impl SelectState {
+    pub fn is_selecting(&self) -> bool {
+        !self.points.is_empty()
+    }
+
+    /// the shape being drawn, a polygon gets a corner at the cursor.
+    pub fn preview(&self, cursor: IVec2, width: u32, height: u32) -> Option<Vec<u8>> {
+        if !self.is_selecting() {
+            return None;
+        }
+        let mut points = self.points.clone();
+        if self.shape == SelectShape::Polygon {
+            points.push(cursor);
+        }
+        Some(self.shape.mask(&points, width, height))
+    }
+}
+
+fn mask_from_fn(width: u32, height: u32, f: impl Fn(IVec2) -> bool) -> Vec<u8> {
+    (0..height as i32)
+        .flat_map(|y| (0..width as i32).map(move |x| IVec2::new(x, y)))
+        .map(|pos| if f(pos) { 255 } else { 0 })
+        .collect()
+}
+
+/// the pixels of the rectangle spanned by `a` and `b`, both corners included.
+pub fn rect_mask(a: IVec2, b: IVec2, width: u32, height: u32) -> Vec<u8> {
+    let (min, max) = (a.min(b), a.max(b));
+    mask_from_fn(width, height, |pos| {
+        pos.cmpge(min).all() && pos.cmple(max).all()
+    })
+}
+
+/// the pixels of the ellipse that fits in the rectangle spanned by `a` and `b`.
+pub fn ellipse_mask(a: IVec2, b: IVec2, width: u32, height: u32) -> Vec<u8> {
+    let (min, max) = (a.min(b).as_vec2(), (a.max(b) + 1).as_vec2());
+    let center = (min + max) / 2.0;
+    let radius = (max - min) / 2.0;
+    mask_from_fn(width, height, |pos| {
+        let d = (pos.as_vec2() + 0.5 - center) / radius;
+        d.length_squared() <= 1.0
+    })
+}
+
+/// the pixels whose centers are inside the polygon through `points`, and the points
+/// themselves so a thin lasso still selects something.
+pub fn polygon_mask(points: &[IVec2], width: u32, height: u32) -> Vec<u8> {
+    let mut mask = vec![0; (width * height) as usize];
+    let corners = points.iter().map(|p| p.as_vec2() + 0.5).collect::<Vec<_>>();
+
+    for y in 0..height {
+        let center = y as f32 + 0.5;
+        // where the edges cross this row, even-odd pairs are inside
+        let mut crossings = corners
+            .iter()
+            .zip(corners.iter().cycle().skip(1))
+            .filter(|(a, b)| (a.y <= center) != (b.y <= center))
+            .map(|(a, b)| a.x + (center - a.y) / (b.y - a.y) * (b.x - a.x))
+            .collect::<Vec<_>>();
+        crossings.sort_by(|a, b| a.total_cmp(b));
+
+        for pair in crossings.chunks_exact(2) {
+            let from = (pair[0] - 0.5).ceil().max(0.0) as u32;
+            let to = (pair[1] - 0.5).floor().min(width as f32 - 1.0);
+            if to < 0.0 {
+                continue;
+            }
+            for x in from..=to as u32 {
+                mask[(y * width + x) as usize] = 255;
+            }
+        }
+    }
+
+    for p in points {
+        if p.x >= 0 && p.y >= 0 && p.x < width as i32 && p.y < height as i32 {
+            mask[(p.y as u32 * width + p.x as u32) as usize] = 255;
+        }
+    }
+    mask
+}
+
+/// combine `mask` with the selection and remember the old one, `what` is named in the label.
+fn select(
+    selection: &mut Selection,
+    history: &mut History,
+    mask: Option<Vec<u8>>,
+    mode: SelectMode,
+    what: &str,
+) {
+    let before = selection.mask.clone();
+    match mask {
+        Some(mask) => selection.combine(mask, mode),
+        None => selection.clear(),
+    }
+
+    if selection.mask != before {
+        let label = if selection.is_empty() {
+            String::from("Deselect")
+        } else {
+            mode.label(what)
+        };
+        info!("{}", label);
+        history.add(label, HistoryItem::Selected(before));
+    }
+}
+
+/// combine the shape that was drawn with the selection.
+fn finish_selection(
+    state: &mut SelectState,
+    selection: &mut Selection,
+    history: &mut History,
+    canvas: &Canvas,
+) {
+    let points = std::mem::take(&mut state.points);
+
+    // a click without dragging deselects
+    let clicked = matches!(state.shape, SelectShape::Rectangle | SelectShape::Ellipse)
+        && points.first() == points.last();
+    let mask = (!clicked || state.mode != SelectMode::Replace)
+        .then(|| state.shape.mask(&points, canvas.width, canvas.height));
+    select(selection, history, mask, state.mode, state.shape.name());
+}
+
+/// [r] : cycle through rectangle, ellipse, lasso and polygon selection
+/// [left mouse] : select, hold shift to add to the selection, alt to subtract or both to
+/// intersect. a click without dragging deselects
+/// [right mouse] : close the polygon
+/// [escape] : stop drawing the polygon
+pub fn select_input(
+    input: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    canvas: Res<Canvas>,
+    mut state: ResMut<SelectState>,
+    mut selection: ResMut<Selection>,
+    mut history: ResMut<History>,
+) {
+    if !input.pressed(KeyCode::LControl) && input.just_pressed(KeyCode::R) {
+        state.shape = state.shape.next();
+        state.points.clear();
+        info!("select shape: {:?}", state.shape);
+    }
+
+    let (Ok(pos) | Err(pos)) = canvas.cursor_position;
+    let pos = pos.floor().as_ivec2();
+
+    if state.shape == SelectShape::Polygon {
+        if input.just_pressed(KeyCode::Escape) {
+            state.points.clear();
+        }
+        if mouse.just_pressed(MouseButton::Left) {
+            if !state.is_selecting() {
+                state.mode = SelectMode::from_input(&input);
+            }
+            state.points.push(pos);
+        }
+        if mouse.just_pressed(MouseButton::Right) && state.points.len() > 2 {
+            finish_selection(&mut state, &mut selection, &mut history, &canvas);
+        }
+        return;
+    }
+
+    if mouse.just_pressed(MouseButton::Left) && canvas.cursor_on_canvas() {
+        state.mode = SelectMode::from_input(&input);
+        state.points = vec![pos];
+    }
+    if !state.is_selecting() {
+        return;
+    }
+    if mouse.pressed(MouseButton::Left) {
+        match state.shape {
+            SelectShape::Lasso => {
+                if state.points.last() != Some(&pos) {
+                    state.points.push(pos);
+                }
+            }
+            _ => {
+                let start = state.points[0];
+                state.points = vec![start, pos];
+            }
+        }
+    }
+    if mouse.just_released(MouseButton::Left) {
+        finish_selection(&mut state, &mut selection, &mut history, &canvas);
+    }
+}
+
+/// how the magic wand picks pixels.
+#[derive(Resource)]
+pub struct WandSettings {
+    /// how far each channel of a color can be from the clicked one
+    pub tolerance: u8,
+    /// only pick pixels connected to the clicked one, otherwise every pixel of the color
+    pub contiguous: bool,
+    /// look at every visible layer instead of the active one
+    pub sample_merged: bool,
+}
+
+impl Default for WandSettings {
+    fn default() -> Self {
+        WandSettings {
+            tolerance: 0,
+            contiguous: true,
+            sample_merged: false,
+        }
+    }
+}
+
+/// the pixels of `image` with a color close to the one at `start`, see [`WandSettings`].
+///
+/// contiguous picks walk the image the same way the bucket does.
+pub fn wand_mask(
+    image: &[u8],
+    start: IVec2,
+    width: u32,
+    height: u32,
+    settings: &WandSettings,
+) -> Vec<u8> {
+    let mut mask = vec![0; (width * height) as usize];
+    if !in_img_bounds(start, width, height) {
+        return mask;
+    }
+    let color = |i: usize| &image[i * 4..i * 4 + 4];
+    let target = color((start.y as u32 * width + start.x as u32) as usize);
+    let matches = |i: usize| {
+        color(i)
+            .iter()
+            .zip(target)
+            .all(|(a, b)| a.abs_diff(*b) <= settings.tolerance)
+    };
+
+    if settings.contiguous {
+        flood_fill(start, |pos| {
+            if !in_img_bounds(pos, width, height) {
+                return false;
+            }
+            let i = (pos.y as u32 * width + pos.x as u32) as usize;
+            if mask[i] > 0 || !matches(i) {
+                return false;
+            }
+            mask[i] = 255;
+            true
+        });
+    } else {
+        for (i, m) in mask.iter_mut().enumerate() {
+            if matches(i) {
+                *m = 255;
+            }
+        }
+    }
+    mask
+}
+
+/// [c] : pick only the pixels connected to the clicked one, or every pixel of its color
+/// [shift + c] : look at the active layer, or every visible layer
+/// [alt + [ / ]] : lower / raise the tolerance
+/// [left mouse] : select the pixels of the clicked color, hold shift to add to the selection,
+/// alt to subtract or both to intersect
+pub fn wand_input(
+    input: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    canvas: Res<Canvas>,
+    tree: LayerTree,
+    images: Res<Assets<Image>>,
+    mut settings: ResMut<WandSettings>,
+    mut selection: ResMut<Selection>,
+    mut history: ResMut<History>,
+) {
+    if input.just_pressed(KeyCode::C) {
+        if input.pressed(KeyCode::LShift) {
+            settings.sample_merged = !settings.sample_merged;
+            info!(
+                "wand samples every visible layer: {}",
+                settings.sample_merged
+            );
+        } else {
+            settings.contiguous = !settings.contiguous;
+            info!("wand contiguous: {}", settings.contiguous);
+        }
+    }
+    if input.pressed(KeyCode::LAlt) {
+        if input.just_pressed(KeyCode::BracketLeft) {
+            settings.tolerance = settings.tolerance.saturating_sub(8);
+            info!("wand tolerance: {}", settings.tolerance);
+        }
+        if input.just_pressed(KeyCode::BracketRight) {
+            settings.tolerance = settings.tolerance.saturating_add(8);
+            info!("wand tolerance: {}", settings.tolerance);
+        }
+    }
+
+    if !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Ok(pos) = canvas.cursor_position else {
+        return;
+    };
+
+    let image = if settings.sample_merged {
+        tree.flatten(canvas.width, canvas.height, None, &images)
+    } else {
+        let Some(image) = tree
+            .layers
+            .get(canvas.layer_id)
+            .ok()
+            .and_then(|(_, handle)| images.get(handle))
+        else {
+            return;
+        };
+        image.data.clone()
+    };
+    let mask = wand_mask(
+        &image,
+        pos.as_ivec2(),
+        canvas.width,
+        canvas.height,
+        &settings,
+    );
+    select(
+        &mut selection,
+        &mut history,
+        Some(mask),
+        SelectMode::from_input(&input),
+        "color",
+    );
+}
+
+/// [ctrl + a] : select everything
+/// [ctrl + shift + a] : deselect
+/// [ctrl + i] : invert the selection
+pub fn selection_input(
+    input: Res<Input<KeyCode>>,
+    canvas: Res<Canvas>,
+    mut selection: ResMut<Selection>,
+    mut history: ResMut<History>,
+) {
+    let len = (canvas.width * canvas.height) as usize;
+    if !selection.is_empty() && selection.mask.len() != len {
+        warn!("the canvas was resized, the selection is cleared");
+        selection.clear();
+    }
+
+    if !input.pressed(KeyCode::LControl) {
+        return;
+    }
+    let before = selection.mask.clone();
+    let label = if input.just_pressed(KeyCode::A) {
+        if input.pressed(KeyCode::LShift) {
+            selection.clear();
+            "Deselect"
+        } else {
+            selection.mask = vec![255; len];
+            "Select all"
+        }
+    } else if input.just_pressed(KeyCode::I) {
+        // without a selection the whole canvas is selected
+        selection.mask = if selection.is_empty() {
+            vec![255; len]
+        } else {
+            selection.mask.iter().map(|m| 255 - m).collect()
+        };
+        selection.clear_if_empty();
+        "Invert selection"
+    } else {
+        return;
+    };
+
+    if selection.mask != before {
+        info!("{}", label);
+        history.add(label, HistoryItem::Selected(before));
+    }
+}
+
+/// the edge of the selection, drawn above the canvas.
+#[derive(Component)]
+pub struct SelectionOutline;
+
+pub fn setup_selection_outline(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let image = images.add(crate::image(1, 1, Color::NONE));
+
+    commands.spawn((
+        SpriteBundle {
+            texture: image,
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 2.0)),
+            ..Default::default()
+        },
+        SelectionOutline,
+    ));
+}
+
+/// draw the pixels of `mask` that touch an unselected pixel as black and white dashes,
+/// `step` moves the dashes along.
+fn draw_ants(data: &mut [u8], mask: &[u8], width: u32, height: u32, step: u32) {
+    let selected = |x: i32, y: i32| {
+        x >= 0
+            && y >= 0
+            && x < width as i32
+            && y < height as i32
+            && mask[(y as u32 * width + x as u32) as usize] > 0
+    };
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            if !selected(x, y)
+                || [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                    .iter()
+                    .all(|(dx, dy)| selected(x + dx, y + dy))
+            {
+                continue;
+            }
+            let color = if (x as u32 + y as u32 + step) / 4 % 2 == 0 {
+                [0, 0, 0, 255]
+            } else {
+                [255, 255, 255, 255]
+            };
+            let i = (y as u32 * width + x as u32) as usize * 4;
+            data[i..i + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+/// marching ants around the selection and the shape being drawn.
+pub fn update_selection_outline(
+    time: Res<Time>,
+    canvas: Res<Canvas>,
+    selection: Res<Selection>,
+    state: Res<SelectState>,
+    outline: Query<&Handle<Image>, With<SelectionOutline>>,
+    mut images: ResMut<Assets<Image>>,
+    mut last_step: Local<u32>,
+    mut last_size: Local<(u32, u32)>,
+    mut last_cursor: Local<IVec2>,
+) {
+    // the ants move 8 pixels a second
+    let step = (time.elapsed_seconds() * 8.0) as u32;
+    let moved = step != *last_step;
+    *last_step = step;
+    let shown = !selection.is_empty() || state.is_selecting();
+    // the canvas changes every frame because of the cursor, only its size matters here
+    let size = (canvas.width, canvas.height);
+    let resized = size != *last_size;
+    *last_size = size;
+    // the shape being drawn follows the cursor
+    let (Ok(cursor) | Err(cursor)) = canvas.cursor_position;
+    let cursor = cursor.floor().as_ivec2();
+    let followed = state.is_selecting() && cursor != *last_cursor;
+    *last_cursor = cursor;
+    if !(selection.is_changed() || state.is_changed() || resized || followed || (shown && moved)) {
+        return;
+    }
+
+    let Some(image) = images.get_mut(outline.single()) else {
+        return;
+    };
+    crate::resize::set_image_size(image, canvas.width, canvas.height);
+    let mut data = vec![0; (canvas.width * canvas.height * 4) as usize];
+
+    if selection.mask.len() == (canvas.width * canvas.height) as usize {
+        draw_ants(
+            &mut data,
+            &selection.mask,
+            canvas.width,
+            canvas.height,
+            step,
+        );
+    }
+    if let Some(preview) = state.preview(cursor, canvas.width, canvas.height) {
+        draw_ants(&mut data, &preview, canvas.width, canvas.height, step);
+    }
+    image.data = data;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `mask` as rows of `#` for selected and `.` for not selected pixels.
+    fn rows(mask: &[u8], width: u32) -> Vec<String> {
+        mask.chunks(width as usize)
+            .map(|row| row.iter().map(|m| if *m > 0 { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn rectangles() {
+        // the corners can be given in any order and the rectangle is cut at the edges
+        let mask = rect_mask(IVec2::new(3, 2), IVec2::new(1, -4), 5, 4);
+        assert_eq!(rows(&mask, 5), [".###.", ".###.", ".###.", "....."]);
+        assert_eq!(
+            rect_mask(IVec2::new(2, 2), IVec2::new(2, 2), 3, 3),
+            [0, 0, 0, 0, 255, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn ellipses() {
+        let mask = ellipse_mask(IVec2::new(4, 4), IVec2::ZERO, 5, 5);
+        assert_eq!(
+            rows(&mask, 5),
+            [".###.", "#####", "#####", "#####", ".###."]
+        );
+        let mask = ellipse_mask(IVec2::new(0, 1), IVec2::new(5, 1), 6, 3);
+        assert_eq!(rows(&mask, 6), ["......", "######", "......"]);
+    }
+
+    #[test]
+    fn polygons() {
+        let triangle = [IVec2::new(0, 0), IVec2::new(4, 0), IVec2::new(0, 4)];
+        assert_eq!(
+            rows(&polygon_mask(&triangle, 5, 5), 5),
+            ["#####", "####.", "###..", "##...", "#...."]
+        );
+
+        // a lasso drawn along a line still selects the pixels it went through
+        let line = [IVec2::new(0, 1), IVec2::new(1, 1), IVec2::new(2, 1)];
+        assert_eq!(
+            rows(&polygon_mask(&line, 4, 3), 4),
+            ["....", "###.", "...."]
+        );
+
+        // corners outside the canvas are fine
+        let square = [
+            IVec2::new(-1, -1),
+            IVec2::new(5, -1),
+            IVec2::new(5, 5),
+            IVec2::new(-1, 5),
+        ];
+        assert_eq!(rows(&polygon_mask(&square, 3, 3), 3), ["###", "###", "###"]);
+    }
+
+    #[test]
+    fn magic_wand() {
+        const A: [u8; 4] = [10, 10, 10, 255];
+        const B: [u8; 4] = [12, 8, 10, 255];
+        const C: [u8; 4] = [200, 0, 0, 255];
+        #[rustfmt::skip]
+        let image = [
+            A, A, C, A,
+            B, C, C, A,
+            A, C, A, A,
+        ]
+        .concat();
+        let settings = |tolerance, contiguous| WandSettings {
+            tolerance,
+            contiguous,
+            sample_merged: false,
+        };
+
+        let mask = wand_mask(&image, IVec2::ZERO, 4, 3, &settings(0, true));
+        assert_eq!(rows(&mask, 4), ["##..", "....", "...."]);
+        let mask = wand_mask(&image, IVec2::ZERO, 4, 3, &settings(2, true));
+        assert_eq!(rows(&mask, 4), ["##..", "#...", "#..."]);
+        let mask = wand_mask(&image, IVec2::ZERO, 4, 3, &settings(0, false));
+        assert_eq!(rows(&mask, 4), ["##.#", "...#", "#.##"]);
+        let mask = wand_mask(&image, IVec2::ZERO, 4, 3, &settings(2, false));
+        assert_eq!(rows(&mask, 4), ["##.#", "#..#", "#.##"]);
+        // clicking outside the image picks nothing
+        let mask = wand_mask(&image, IVec2::new(4, 0), 4, 3, &settings(255, false));
+        assert!(mask.iter().all(|m| *m == 0));
+    }
+
+    #[test]
+    fn bounds() {
+        assert_eq!(Selection::default().bounds(4), None);
+        let selection = Selection { mask: vec![0; 12] };
+        assert_eq!(selection.bounds(4), None);
+
+        let selection = Selection {
+            mask: polygon_mask(
+                &[IVec2::new(2, 0), IVec2::new(1, 1), IVec2::new(3, 2)],
+                4,
+                3,
+            ),
+        };
+        assert_eq!(
+            selection.bounds(4),
+            Some((UVec2::new(1, 0), UVec2::new(3, 2)))
+        );
+    }
+}
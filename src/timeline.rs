@@ -1,16 +1,215 @@
 use std::ops::Range;
 
-use bevy::{prelude::*, utils::HashSet};
+use bevy::{ecs::entity::Entities, prelude::*, utils::HashSet};
+use serde::{Deserialize, Serialize};
 
-use crate::layer::Layer;
+use crate::{
+    canvas::Canvas,
+    group::{Detached, LayerGroup},
+    layer::{Layer, LayerBundle},
+    tools::MoveState,
+    History, HistoryItem,
+};
+
+/// how long a frame is shown if it wasn't given a duration, in milliseconds.
+pub const DEFAULT_DURATION: u32 = 100;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TagDirection {
+    #[default]
+    Forward,
+    Reverse,
+    PingPong,
+}
+
+impl TagDirection {
+    pub fn next(self) -> Self {
+        match self {
+            TagDirection::Forward => TagDirection::Reverse,
+            TagDirection::Reverse => TagDirection::PingPong,
+            TagDirection::PingPong => TagDirection::Forward,
+        }
+    }
+}
+
+/// a named range of frames, like "idle" or "run".
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Tag {
+    pub name: String,
+    /// the first frame
+    pub from: i32,
+    /// the last frame, inclusive
+    pub to: i32,
+    pub direction: TagDirection,
+}
+
+impl Tag {
+    pub fn contains(&self, frame: i32) -> bool {
+        (self.from..=self.to).contains(&frame)
+    }
+
+    /// the frames of one pass through the tag in its direction.
+    pub fn sequence(&self) -> Vec<i32> {
+        sequence(
+            self.from,
+            self.to,
+            self.direction == TagDirection::Reverse,
+            self.direction == TagDirection::PingPong,
+        )
+    }
+}
+
+/// the frames from `first` to `last` (inclusive) in playback order,
+/// ping-pong goes there and back without showing the ends twice.
+pub fn sequence(first: i32, last: i32, reverse: bool, ping_pong: bool) -> Vec<i32> {
+    let mut frames = (first..=last).collect::<Vec<_>>();
+    if reverse {
+        frames.reverse();
+    }
+    if ping_pong && frames.len() > 2 {
+        let back = frames[1..frames.len() - 1]
+            .iter()
+            .rev()
+            .copied()
+            .collect::<Vec<_>>();
+        frames.extend(back);
+    }
+    frames
+}
 
 #[derive(Component)]
 pub struct Timeline {
     layers: Vec<Entity>,
+    /// the frame of the [`GlobalTimeline`] this timeline starts at
     frame_offset: i32,
+    /// how long each frame is shown in milliseconds, missing ones use [`DEFAULT_DURATION`]
+    durations: Vec<u32>,
+    tags: Vec<Tag>,
 }
 
 impl Timeline {
+    pub fn new(layers: Vec<Entity>, frame_offset: i32) -> Self {
+        Self {
+            layers,
+            frame_offset,
+            durations: Vec::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn with_animation(mut self, durations: Vec<u32>, tags: Vec<Tag>) -> Self {
+        self.durations = durations;
+        self.tags = tags;
+        self
+    }
+
+    pub fn durations(&self) -> &[u32] {
+        &self.durations
+    }
+
+    pub fn durations_mut(&mut self) -> &mut Vec<u32> {
+        &mut self.durations
+    }
+
+    /// how long `frame` is shown in milliseconds.
+    pub fn duration(&self, frame: i32) -> u32 {
+        usize::try_from(frame)
+            .ok()
+            .and_then(|i| self.durations.get(i))
+            .copied()
+            .unwrap_or(DEFAULT_DURATION)
+    }
+
+    pub fn set_duration(&mut self, frame: i32, duration: u32) {
+        let Ok(i) = usize::try_from(frame) else {
+            return;
+        };
+        if self.durations.len() <= i {
+            self.durations.resize(i + 1, DEFAULT_DURATION);
+        }
+        self.durations[i] = duration.max(1);
+    }
+
+    /// make room for a new frame at `frame`, see [`Layer::insert_frame`].
+    ///
+    /// tags after `frame` move along, a tag the frame is inserted into grows.
+    pub fn insert_frame(&mut self, frame: i32, duration: u32) {
+        match usize::try_from(frame) {
+            Ok(i) if i < self.durations.len() => self.durations.insert(i, duration.max(1)),
+            _ => self.set_duration(frame, duration),
+        }
+        for tag in &mut self.tags {
+            if tag.from >= frame {
+                tag.from += 1;
+            }
+            if tag.to >= frame {
+                tag.to += 1;
+            }
+        }
+    }
+
+    /// remove the duration of `frame` and return it, see [`Layer::remove_frame`].
+    ///
+    /// tags after `frame` move along, a tag containing it shrinks and is removed once it's
+    /// empty.
+    pub fn remove_frame(&mut self, frame: i32) -> u32 {
+        let duration = self.duration(frame);
+        match usize::try_from(frame) {
+            Ok(i) if i < self.durations.len() => {
+                self.durations.remove(i);
+            }
+            _ => (),
+        }
+        for tag in &mut self.tags {
+            if tag.from > frame {
+                tag.from -= 1;
+            }
+            if tag.to >= frame {
+                tag.to -= 1;
+            }
+        }
+        self.tags.retain(|tag| tag.from <= tag.to);
+        duration
+    }
+
+    /// tags cover frame numbers, they stay where they are.
+    pub fn swap_frames(&mut self, a: i32, b: i32) {
+        let (duration_a, duration_b) = (self.duration(a), self.duration(b));
+        self.set_duration(a, duration_b);
+        self.set_duration(b, duration_a);
+    }
+
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    pub fn tags_mut(&mut self) -> &mut Vec<Tag> {
+        &mut self.tags
+    }
+
+    /// the index of the last tag containing `frame`.
+    pub fn tag_at(&self, frame: i32) -> Option<usize> {
+        self.tags.iter().rposition(|tag| tag.contains(frame))
+    }
+
+    pub fn layers(&self) -> &[Entity] {
+        &self.layers
+    }
+
+    /// replace the top level layers and groups, returns the old ones.
+    pub fn set_layers(&mut self, layers: Vec<Entity>) -> Vec<Entity> {
+        std::mem::replace(&mut self.layers, layers)
+    }
+
+    pub fn frame_offset(&self) -> i32 {
+        self.frame_offset
+    }
+
+    /// the frame of this timeline shown at `frame` of the [`GlobalTimeline`].
+    pub fn local_frame(&self, frame: i32) -> i32 {
+        frame - self.frame_offset
+    }
+
     pub fn remove_layer(&mut self, index: usize, commands: &mut Commands) -> Option<Entity> {
         assert!(self.layers.len() > index);
 
@@ -20,31 +219,343 @@ impl Timeline {
         Some(layer)
     }
 
-    pub fn add_layer(&mut self, _commands: &mut Commands) -> Entity {
-        todo!()
+    /// spawn `layer` at `index`, the layer's `timeline_id` is set by [`timeline_layer_fix`].
+    pub fn add_layer(
+        &mut self,
+        index: usize,
+        layer: LayerBundle,
+        commands: &mut Commands,
+    ) -> Entity {
+        let layer = commands.spawn(layer).id();
+        self.layers.insert(index.min(self.layers.len()), layer);
+        layer
+    }
+
+    /// insert an already spawned layer or group at `index`.
+    pub fn insert(&mut self, index: usize, node: Entity) {
+        self.layers.insert(index.min(self.layers.len()), node);
+    }
+
+    /// take the layer or group at `index` out of the timeline without despawning it.
+    pub fn remove(&mut self, index: usize) -> Entity {
+        self.layers.remove(index)
+    }
+
+    pub fn move_layer(&mut self, from: usize, to: usize) {
+        let layer = self.layers.remove(from);
+        self.layers.insert(to.min(self.layers.len()), layer);
+    }
+
+    pub fn index_of(&self, layer: Entity) -> Option<usize> {
+        self.layers.iter().position(|id| *id == layer)
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaybackMode {
+    #[default]
+    Loop,
+    Once,
+    PingPong,
+}
+
+impl PlaybackMode {
+    pub fn next(self) -> Self {
+        match self {
+            PlaybackMode::Loop => PlaybackMode::Once,
+            PlaybackMode::Once => PlaybackMode::PingPong,
+            PlaybackMode::PingPong => PlaybackMode::Loop,
+        }
     }
 }
 
 #[derive(Resource)]
 pub struct GlobalTimeline {
+    /// the current frame, the fraction is how far along it the playback is
     frame: f32,
     frame_range: Range<u16>,
     playing: bool,
     /// if empty then all timelines will be played
     timelines: HashSet<Entity>,
+    mode: PlaybackMode,
+    /// play the frame range backwards
+    reverse: bool,
+    /// 1 or -1, changes when playing back and forth
+    direction: i32,
+    /// the timeline and index of the tag that's played, the frame range follows it
+    tag: Option<(Entity, usize)>,
+}
+
+impl Default for GlobalTimeline {
+    fn default() -> Self {
+        GlobalTimeline {
+            frame: 0.0,
+            frame_range: 0..1,
+            playing: false,
+            timelines: HashSet::new(),
+            mode: PlaybackMode::Loop,
+            reverse: false,
+            direction: 1,
+            tag: None,
+        }
+    }
+}
+
+impl GlobalTimeline {
+    pub fn current_frame(&self) -> i32 {
+        self.frame.floor() as i32
+    }
+
+    pub fn frame_range(&self) -> Range<u16> {
+        self.frame_range.clone()
+    }
+
+    /// the range can't be empty, the current frame is moved inside it.
+    pub fn set_frame_range(&mut self, range: Range<u16>) {
+        self.frame_range = range.start..range.end.max(range.start + 1);
+        self.go_to(self.current_frame());
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// does `timeline` follow the playback.
+    pub fn plays(&self, timeline: Entity) -> bool {
+        self.timelines.is_empty() || self.timelines.contains(&timeline)
+    }
+
+    pub fn toggle_playing(&mut self) {
+        self.playing = !self.playing;
+        self.direction = if self.reverse { -1 } else { 1 };
+        // start over if it stopped at the end
+        let (first, last) = self.bounds();
+        let (start, end) = if self.reverse {
+            (last, first)
+        } else {
+            (first, last)
+        };
+        if self.playing && self.mode == PlaybackMode::Once && self.current_frame() == end {
+            self.frame = start as f32;
+        }
+    }
+
+    pub fn mode(&self) -> PlaybackMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: PlaybackMode) {
+        self.mode = mode;
+        self.direction = if self.reverse { -1 } else { 1 };
+    }
+
+    pub fn tag(&self) -> Option<(Entity, usize)> {
+        self.tag
+    }
+
+    /// play the tag at `index` of `timeline` in its direction, or everything if `None`.
+    ///
+    /// the frame range is updated by [`fit_frame_range`].
+    pub fn set_tag(&mut self, tag: Option<(Entity, usize)>, direction: TagDirection) {
+        self.tag = tag;
+        self.reverse = direction == TagDirection::Reverse;
+        self.set_mode(match direction {
+            TagDirection::PingPong => PlaybackMode::PingPong,
+            _ => PlaybackMode::Loop,
+        });
+    }
+
+    /// the frames of one pass through the frame range in playback order.
+    pub fn sequence(&self) -> Vec<i32> {
+        let (first, last) = self.bounds();
+        sequence(
+            first,
+            last,
+            self.reverse,
+            self.mode == PlaybackMode::PingPong,
+        )
+    }
+
+    /// jump to `frame`, clamped to the frame range.
+    pub fn go_to(&mut self, frame: i32) {
+        let (first, last) = self.bounds();
+        self.frame = frame.clamp(first, last) as f32;
+    }
+
+    /// go to the next or previous frame, wrapping around at the ends of the range.
+    pub fn step(&mut self, forward: bool) {
+        let (first, last) = self.bounds();
+        let frame = self.current_frame() + if forward { 1 } else { -1 };
+        self.frame = match frame {
+            _ if frame > last => first,
+            _ if frame < first => last,
+            _ => frame,
+        } as f32;
+    }
+
+    /// move the playback forward by `seconds`, following the playback mode.
+    ///
+    /// `duration` is how long a frame is shown in milliseconds.
+    pub fn advance(&mut self, seconds: f32, duration: impl Fn(i32) -> u32) {
+        let duration = |frame| duration(frame).max(1) as f32;
+        let (first, last) = self.bounds();
+        let mut frame = self.current_frame().clamp(first, last);
+        let mut ms = self.frame.fract() * duration(frame) + seconds * 1000.0;
+
+        while ms >= duration(frame) && self.playing {
+            ms -= duration(frame);
+            let next = frame + self.direction;
+            frame = match self.mode {
+                _ if (first..=last).contains(&next) => next,
+                PlaybackMode::Loop if next > last => first,
+                PlaybackMode::Loop => last,
+                PlaybackMode::Once => {
+                    self.playing = false;
+                    ms = 0.0;
+                    frame
+                }
+                PlaybackMode::PingPong => {
+                    self.direction = -self.direction;
+                    (frame + self.direction).clamp(first, last)
+                }
+            };
+        }
+        self.frame = frame as f32 + (ms / duration(frame)).min(0.999);
+    }
+
+    /// the first and last frame of the range
+    fn bounds(&self) -> (i32, i32) {
+        (
+            self.frame_range.start as i32,
+            self.frame_range.end as i32 - 1,
+        )
+    }
+}
+
+/// play the animation using the frame durations of the active timeline.
+pub fn playback(
+    time: Res<Time>,
+    mut global_timeline: ResMut<GlobalTimeline>,
+    canvas: Res<Canvas>,
+    layers: Query<&Layer>,
+    timelines: Query<&Timeline>,
+) {
+    if !global_timeline.playing {
+        return;
+    }
+    let timeline = layers
+        .get(canvas.layer_id)
+        .ok()
+        .and_then(|layer| timelines.get(layer.timeline_id?).ok());
+
+    global_timeline.advance(time.delta_seconds(), |frame| {
+        timeline.map_or(DEFAULT_DURATION, |timeline| {
+            timeline.duration(timeline.local_frame(frame))
+        })
+    });
+}
+
+/// show the current frame of every layer in a playing timeline.
+pub fn update_layer_textures(
+    global_timeline: Res<GlobalTimeline>,
+    timelines: Query<(Entity, &Timeline)>,
+    mut layers: Query<(&Layer, &mut Handle<Image>)>,
+) {
+    let frame = global_timeline.current_frame();
+
+    for (layer, mut texture) in &mut layers {
+        let Some((timeline_id, timeline)) = layer.timeline_id.and_then(|id| timelines.get(id).ok())
+        else {
+            continue;
+        };
+        if !global_timeline.plays(timeline_id) {
+            continue;
+        }
+
+        // a missing frame is an empty cel
+        let handle = layer
+            .frames
+            .get(&timeline.local_frame(frame))
+            .cloned()
+            .unwrap_or_default();
+        if *texture != handle {
+            *texture = handle;
+        }
+    }
+}
+
+/// [enter] : play / pause
+/// [shift + enter] : cycle through loop, once and ping-pong playback
+/// [ctrl + left / right] : step one frame back / forward
+pub fn timeline_input(
+    input: Res<Input<KeyCode>>,
+    mut global_timeline: ResMut<GlobalTimeline>,
+    move_state: Res<MoveState>,
+) {
+    // enter puts down the pixels held by the move tool
+    if !input.pressed(KeyCode::LControl)
+        && input.just_pressed(KeyCode::Return)
+        && !move_state.is_floating()
+    {
+        if input.pressed(KeyCode::LShift) {
+            let mode = global_timeline.mode.next();
+            global_timeline.set_mode(mode);
+            info!("playback: {:?}", mode);
+        } else {
+            global_timeline.toggle_playing();
+            info!("playing: {}", global_timeline.playing);
+        }
+    }
+
+    if input.pressed(KeyCode::LControl) && !input.pressed(KeyCode::LShift) {
+        if input.just_pressed(KeyCode::Right) {
+            global_timeline.step(true);
+            info!("frame: {}", global_timeline.current_frame());
+        }
+        if input.just_pressed(KeyCode::Left) {
+            global_timeline.step(false);
+            info!("frame: {}", global_timeline.current_frame());
+        }
+    }
 }
 
 pub fn timeline_layer_fix(
     mut timelines: Query<(Entity, &mut Timeline)>,
-    mut layers: Query<(Entity, &mut Layer)>,
+    mut layers: Query<(Entity, &mut Layer, Option<&Parent>), Without<Detached>>,
+    mut groups: Query<
+        (Entity, &mut LayerGroup, Option<&Parent>, Option<&Children>),
+        Without<Detached>,
+    >,
+    detached: Query<(), With<Detached>>,
+    entities: &Entities,
     mut global_timeline: ResMut<GlobalTimeline>,
 ) {
+    // the top level layers and groups, the ones in a group are kept by the group
+    let nodes = layers
+        .iter()
+        .map(|(id, layer, parent)| (id, layer.timeline_id, parent.is_some()))
+        .chain(
+            groups
+                .iter()
+                .map(|(id, group, parent, _)| (id, group.timeline_id, parent.is_some())),
+        )
+        .collect::<Vec<_>>();
+
     // add a layer entity to the timeline if it doesn't already exist
-    for (layer_id, layer) in &layers {
+    for (layer_id, timeline_id, in_group) in nodes {
         if let Some((timeline_id, mut timeline)) =
-            layer.timeline_id.and_then(|id| timelines.get_mut(id).ok())
+            timeline_id.and_then(|id| timelines.get_mut(id).ok())
         {
-            if !timeline.layers.contains(&layer_id) {
+            let index = timeline.index_of(layer_id);
+            if in_group {
+                if let Some(i) = index {
+                    timeline.layers.remove(i);
+                    warn!(
+                        "layer: {:?} is in a group but was also in timeline: {:?}",
+                        layer_id, timeline_id
+                    );
+                }
+            } else if index.is_none() {
                 timeline.layers.push(layer_id);
                 warn!(
                     "layer: {:?} was missing from timeline: {:?}",
@@ -54,13 +565,15 @@ pub fn timeline_layer_fix(
         }
     }
 
-    // remove layers from the timeline that does not exist in the world
+    // remove layers from the timeline that does not exist in the world, the ones spawned
+    // this frame aren't in the queries yet but they already exist
     for (timeline_id, mut timeline) in &mut timelines {
         let mut i = 0;
+        let mut stack = Vec::new();
         while i < timeline.layers.len() {
-            let layer_id = &timeline.layers[i];
-            if let Ok((_, mut layer)) = layers.get_mut(*layer_id) {
-                layer.timeline_id = Some(timeline_id);
+            let layer_id = timeline.layers[i];
+            if entities.contains(layer_id) && !detached.contains(layer_id) {
+                stack.push(layer_id);
                 i += 1;
             } else {
                 timeline.layers.remove(i);
@@ -70,6 +583,20 @@ pub fn timeline_layer_fix(
                 );
             }
         }
+
+        // everything in the timeline and its groups belongs to it
+        while let Some(id) = stack.pop() {
+            if let Ok((_, mut layer, _)) = layers.get_mut(id) {
+                if layer.timeline_id != Some(timeline_id) {
+                    layer.timeline_id = Some(timeline_id);
+                }
+            } else if let Ok((_, mut group, _, children)) = groups.get_mut(id) {
+                if group.timeline_id != Some(timeline_id) {
+                    group.timeline_id = Some(timeline_id);
+                }
+                stack.extend(children.into_iter().flatten());
+            }
+        }
     }
 
     // remove timeline from global timeline if it does not exist in the world
@@ -84,3 +611,309 @@ pub fn timeline_layer_fix(
         });
     }
 }
+
+/// the number of frames needed to show every frame of every timeline.
+pub fn total_frame_count<'a>(
+    layers: impl IntoIterator<Item = &'a Layer>,
+    timelines: &Query<&Timeline>,
+) -> i32 {
+    layers
+        .into_iter()
+        .filter_map(|layer| {
+            let timeline = timelines.get(layer.timeline_id?).ok()?;
+            Some(timeline.frame_offset() + layer.frame_count())
+        })
+        .max()
+        .unwrap_or(1)
+}
+
+/// keep the frame range of the global timeline the length of the animation, or of the tag
+/// that's played.
+pub fn fit_frame_range(
+    mut global_timeline: ResMut<GlobalTimeline>,
+    layers: Query<&Layer>,
+    timelines: Query<&Timeline>,
+) {
+    let tag_range = global_timeline.tag.and_then(|(timeline_id, i)| {
+        let timeline = timelines.get(timeline_id).ok()?;
+        let tag = timeline.tags().get(i)?;
+        let from = (tag.from + timeline.frame_offset()).max(0) as u16;
+        let to = (tag.to + timeline.frame_offset()).max(0) as u16;
+        Some(from..to + 1)
+    });
+    let range = tag_range.unwrap_or_else(|| {
+        let count = total_frame_count(&layers, &timelines).max(1) as u16;
+        0..count
+    });
+    if global_timeline.frame_range != range {
+        global_timeline.set_frame_range(range);
+    }
+}
+
+/// [alt + n] : insert an empty frame after the current one
+/// [alt + j] : duplicate the current frame
+/// [alt + delete] : delete the current frame
+/// [ctrl + shift + left / right] : move the current frame left / right
+///
+/// frames are added to and removed from every layer of the active timeline so they stay aligned.
+pub fn frame_input(
+    input: Res<Input<KeyCode>>,
+    canvas: Res<Canvas>,
+    mut global_timeline: ResMut<GlobalTimeline>,
+    mut timelines: Query<&mut Timeline>,
+    mut layers: Query<(Entity, &mut Layer)>,
+    mut images: ResMut<Assets<Image>>,
+    mut history: ResMut<History>,
+    move_state: Res<MoveState>,
+) {
+    let Some((timeline_id, mut timeline)) = layers
+        .get(canvas.layer_id)
+        .ok()
+        .and_then(|(_, layer)| layer.timeline_id)
+        .and_then(|id| Some((id, timelines.get_mut(id).ok()?)))
+    else {
+        return;
+    };
+
+    let alt = input.pressed(KeyCode::LAlt);
+    let ctrl = input.pressed(KeyCode::LControl);
+    let shift = input.pressed(KeyCode::LShift);
+
+    // the move tool holds on to the frame it lifted the pixels from
+    let edit = alt && input.any_just_pressed([KeyCode::N, KeyCode::J, KeyCode::Delete])
+        || ctrl && shift && input.any_just_pressed([KeyCode::Left, KeyCode::Right]);
+    if edit && move_state.is_floating() {
+        warn!("put the moved pixels down before changing the frames");
+        return;
+    }
+
+    let frame = timeline.local_frame(global_timeline.current_frame());
+    let count = layers
+        .iter()
+        .filter(|(_, layer)| layer.timeline_id == Some(timeline_id))
+        .map(|(_, layer)| layer.frame_count())
+        .max()
+        .unwrap_or(1);
+
+    // inserting and removing frames shifts the tags, which can't always be shifted back
+    let (label, item) = if alt && input.just_pressed(KeyCode::N) {
+        let tags = timeline.tags().to_vec();
+        let duration = timeline.duration(frame);
+        timeline.insert_frame(frame + 1, duration);
+        for (_, mut layer) in &mut layers {
+            if layer.timeline_id == Some(timeline_id) {
+                layer.insert_frame(frame + 1, None);
+            }
+        }
+        info!("inserted frame {}", frame + 1);
+        let item = HistoryItem::Frame {
+            timeline: timeline_id,
+            frame: frame + 1,
+            layers: Vec::new(),
+            duration,
+            tags,
+            inserted: true,
+        };
+        (format!("Insert frame {}", frame + 1), item)
+    } else if alt && input.just_pressed(KeyCode::J) {
+        let tags = timeline.tags().to_vec();
+        let duration = timeline.duration(frame);
+        timeline.insert_frame(frame + 1, duration);
+        for (_, mut layer) in &mut layers {
+            if layer.timeline_id != Some(timeline_id) {
+                continue;
+            }
+            let copy = layer
+                .frames
+                .get(&frame)
+                .and_then(|handle| images.get(handle))
+                .cloned()
+                .map(|image| images.add(image));
+            layer.insert_frame(frame + 1, copy);
+        }
+        info!("duplicated frame {}", frame);
+        let item = HistoryItem::Frame {
+            timeline: timeline_id,
+            frame: frame + 1,
+            layers: Vec::new(),
+            duration,
+            tags,
+            inserted: true,
+        };
+        (format!("Duplicate frame {}", frame), item)
+    } else if alt && input.just_pressed(KeyCode::Delete) {
+        if count <= 1 {
+            warn!("can't delete the only frame");
+            return;
+        }
+        let tags = timeline.tags().to_vec();
+        let duration = timeline.remove_frame(frame);
+        let removed = layers
+            .iter_mut()
+            .filter(|(_, layer)| layer.timeline_id == Some(timeline_id))
+            .map(|(layer_id, mut layer)| (layer_id, layer.take_cel(frame, &images)))
+            .collect();
+        info!("deleted frame {}", frame);
+        let item = HistoryItem::Frame {
+            timeline: timeline_id,
+            frame,
+            layers: removed,
+            duration,
+            tags,
+            inserted: false,
+        };
+        (format!("Delete frame {}", frame), item)
+    } else if ctrl && shift && input.any_just_pressed([KeyCode::Left, KeyCode::Right]) {
+        let other = if input.just_pressed(KeyCode::Right) {
+            frame + 1
+        } else {
+            frame - 1
+        };
+        if other < 0 || other >= count {
+            return;
+        }
+        timeline.swap_frames(frame, other);
+        for (_, mut layer) in &mut layers {
+            if layer.timeline_id == Some(timeline_id) {
+                layer.swap_frames(frame, other);
+            }
+        }
+        info!("moved frame {} to {}", frame, other);
+        let item = HistoryItem::FramesSwapped {
+            timeline: timeline_id,
+            a: frame,
+            b: other,
+        };
+        (format!("Move frame {} to {}", frame, other), item)
+    } else {
+        return;
+    };
+
+    // follow the frame that was added or moved
+    let next = match &item {
+        HistoryItem::Frame { frame, .. } => *frame,
+        HistoryItem::FramesSwapped { b, .. } => *b,
+        _ => frame,
+    };
+    let offset = timeline.frame_offset();
+    let count = total_frame_count(
+        layers.iter().map(|(_, layer)| layer),
+        &timelines.to_readonly(),
+    );
+    global_timeline.set_frame_range(0..count.max(1) as u16);
+    global_timeline.go_to(next + offset);
+
+    history.add(label, item);
+}
+
+/// [alt + - / =] : shorten / lengthen the current frame by 10 milliseconds
+/// [alt + t] : start a tag at the current frame, press again on another frame to end it
+/// [alt + shift + t] : remove the tag at the current frame
+/// [alt + d] : cycle the direction of the tag at the current frame
+/// [alt + p] : cycle through playing every tag of the timeline, then everything
+pub fn tag_input(
+    input: Res<Input<KeyCode>>,
+    canvas: Res<Canvas>,
+    mut global_timeline: ResMut<GlobalTimeline>,
+    layers: Query<&Layer>,
+    mut timelines: Query<&mut Timeline>,
+    mut history: ResMut<History>,
+    mut tag_start: Local<Option<i32>>,
+) {
+    if !input.pressed(KeyCode::LAlt) {
+        return;
+    }
+    let Some((timeline_id, mut timeline)) = layers
+        .get(canvas.layer_id)
+        .ok()
+        .and_then(|layer| layer.timeline_id)
+        .and_then(|id| Some((id, timelines.get_mut(id).ok()?)))
+    else {
+        return;
+    };
+    let frame = timeline.local_frame(global_timeline.current_frame());
+    let (durations, tags) = (timeline.durations().to_vec(), timeline.tags().to_vec());
+    // what changed, if anything
+    let mut label = None;
+
+    for (key, delta) in [(KeyCode::Minus, -10), (KeyCode::Equals, 10)] {
+        if input.just_pressed(key) {
+            let duration = (timeline.duration(frame) as i32 + delta).max(10);
+            timeline.set_duration(frame, duration as u32);
+            label = Some(format!("Frame {} duration to {}ms", frame, duration));
+            info!("frame {} duration: {}ms", frame, duration);
+        }
+    }
+
+    if input.just_pressed(KeyCode::T) {
+        if input.pressed(KeyCode::LShift) {
+            if let Some(i) = timeline.tag_at(frame) {
+                let tag = timeline.tags_mut().remove(i);
+                if global_timeline.tag().is_some() {
+                    global_timeline.set_tag(None, TagDirection::Forward);
+                }
+                label = Some(format!("Remove tag {}", tag.name));
+                info!("removed tag {}", tag.name);
+            }
+        } else if let Some(start) = tag_start.take() {
+            let name = format!("Tag {}", timeline.tags().len() + 1);
+            info!(
+                "added tag {} from {} to {}",
+                name,
+                start.min(frame),
+                start.max(frame)
+            );
+            label = Some(format!("Add tag {}", name));
+            timeline.tags_mut().push(Tag {
+                name,
+                from: start.min(frame),
+                to: start.max(frame),
+                direction: TagDirection::Forward,
+            });
+        } else {
+            *tag_start = Some(frame);
+            info!("tag starts at frame {}", frame);
+        }
+    }
+
+    if input.just_pressed(KeyCode::D) {
+        if let Some(i) = timeline.tag_at(frame) {
+            let tag = &mut timeline.tags_mut()[i];
+            tag.direction = tag.direction.next();
+            let direction = tag.direction;
+            info!("{} direction: {:?}", tag.name, direction);
+            label = Some(format!("{} direction to {:?}", tag.name, direction));
+            if global_timeline.tag() == Some((timeline_id, i)) {
+                global_timeline.set_tag(Some((timeline_id, i)), direction);
+            }
+        }
+    }
+
+    if let Some(label) = label {
+        history.add(
+            label,
+            HistoryItem::Animation {
+                timeline: timeline_id,
+                durations,
+                tags,
+            },
+        );
+    }
+
+    if input.just_pressed(KeyCode::P) {
+        let next = match global_timeline.tag() {
+            Some((id, i)) if id == timeline_id => i + 1,
+            _ => 0,
+        };
+        match timeline.tags().get(next) {
+            Some(tag) => {
+                info!("playing tag {}", tag.name);
+                global_timeline.set_tag(Some((timeline_id, next)), tag.direction);
+            }
+            None => {
+                info!("playing every frame");
+                global_timeline.set_tag(None, TagDirection::Forward);
+            }
+        }
+    }
+}
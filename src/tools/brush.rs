@@ -1,4 +1,10 @@
-use crate::{canvas::Canvas, layer::Layer, ColorPalette, History, HistoryItem, ImagePaint};
+use crate::{
+    canvas::Canvas,
+    delta::Delta,
+    layer::{ActiveFrame, Target},
+    selection::Selection,
+    ColorPalette, History, HistoryItem, ImagePaint,
+};
 
 use super::*;
 
@@ -26,6 +32,10 @@ pub struct BrushState {
     last_position: Option<Vec2>,
     pub mode: BrushMode,
     is_cleared: bool,
+    /// the selection when the stroke started, nothing outside of it is drawn
+    pub selection: Selection,
+    /// the frame the stroke draws on
+    target: Option<Target>,
 }
 
 impl BrushState {
@@ -52,6 +62,9 @@ impl BrushState {
     }
 
     pub fn draw_point(&mut self, pos: IVec2, width: u32) {
+        if !self.selection.contains(pos, width) {
+            return;
+        }
         self.is_cleared = false;
 
         let idx = ((pos.x + pos.y * width as i32) * 4) as usize;
@@ -88,12 +101,14 @@ impl BrushState {
 
         let mut err = dx + dy;
         loop {
-            let idx = (y * width as i32 + x) as usize * 4;
+            if self.selection.contains(IVec2::new(x, y), width) {
+                let idx = (y * width as i32 + x) as usize * 4;
 
-            self.buffer[idx] = color[0];
-            self.buffer[idx + 1] = color[1];
-            self.buffer[idx + 2] = color[2];
-            self.buffer[idx + 3] = color[3];
+                self.buffer[idx] = color[0];
+                self.buffer[idx + 1] = color[1];
+                self.buffer[idx + 2] = color[2];
+                self.buffer[idx + 3] = color[3];
+            }
 
             if x == end.x && y == end.y {
                 break;
@@ -117,13 +132,17 @@ impl BrushState {
     pub fn get_updated_buffer(&self) -> Option<Vec<u8>> {
         let mut new = Vec::with_capacity(self.buffer.len());
 
-        for ([r1, g1, b1, a1], [r2, g2, b2, a2]) in self
+        for (i, ([r1, g1, b1, a1], [r2, g2, b2, a2])) in self
             .data
             .as_ref()?
             .array_chunks::<4>()
             .zip(self.buffer.array_chunks::<4>())
+            .enumerate()
         {
-            if self.is_eraser() {
+            if !self.selection.contains_index(i) {
+                // pixels outside the selection are left as they were
+                new.extend([r1, g1, b1, a1]);
+            } else if self.is_eraser() {
                 if *a2 == 255 {
                     new.extend([0, 0, 0, 0]);
                 } else {
@@ -148,30 +167,23 @@ impl BrushState {
         Some(new)
     }
 
-    fn clone_data_from_image(
-        &mut self,
-        canvas: &Res<Canvas>,
-        layers: &Query<&Layer>,
-        images: &mut ResMut<Assets<Image>>,
-    ) {
-        let layer = layers.get(canvas.layer_id).unwrap();
-        let image = images.get_mut(&layer.frames[&0]).unwrap();
-        self.data = Some(image.data.clone());
+    fn clone_data_from_image(&mut self, images: &Assets<Image>) {
+        let Some(target) = &self.target else {
+            return;
+        };
+        self.data = images.get(&target.handle).map(|image| image.data.clone());
     }
 
-    fn apply_buffer_to_layer(
-        &mut self,
-        canvas: &Res<Canvas>,
-        layers: &Query<&Layer>,
-        images: &mut ResMut<Assets<Image>>,
-    ) {
-        let layer = layers.get(canvas.layer_id).unwrap();
-        let image = images.get_mut(&layer.frames[&0]).unwrap();
+    fn apply_buffer_to_layer(&mut self, images: &mut Assets<Image>) {
+        let Some(target) = &self.target else {
+            return;
+        };
+        let Some(image) = images.get_mut(&target.handle) else {
+            return;
+        };
         if self.data.is_none() {
             self.data = Some(image.data.clone());
         }
-        let layer = layers.get(canvas.layer_id).unwrap();
-        let image = images.get_mut(&layer.frames[&0]).unwrap();
         image.data = self.get_updated_buffer().unwrap();
     }
 }
@@ -179,9 +191,10 @@ impl BrushState {
 pub fn start_painting(
     mut brush: ResMut<BrushState>,
     canvas: Res<Canvas>,
-    layers: Query<&Layer>,
+    mut frame: ActiveFrame,
     mut images: ResMut<Assets<Image>>,
     keyborad: Res<Input<KeyCode>>,
+    selection: Res<Selection>,
 ) {
     info!("started painting!");
 
@@ -192,31 +205,50 @@ pub fn start_painting(
         brush.mode = BrushMode::Pixel;
     }
 
-    brush.clone_data_from_image(&canvas, &layers, &mut images);
-    if !brush.is_cleared {
+    brush.selection = selection.clone();
+    brush.target = Some(frame.target(&mut images));
+    brush.clone_data_from_image(&images);
+    if !brush.is_cleared || brush.buffer.len() != (canvas.width * canvas.height * 4) as usize {
         brush.clear_buffer(canvas.width, canvas.height);
     }
     if let Ok(pos) = canvas.cursor_position {
         brush.start_position = Some(pos);
         brush.draw_point(pos.as_ivec2(), canvas.width);
-        brush.apply_buffer_to_layer(&canvas, &layers, &mut images);
+        brush.apply_buffer_to_layer(&mut images);
     } else {
         brush.start_position = None;
     }
     brush.last_position = canvas.cursor_position.ok();
 }
 
-pub fn stop_painting(mut brush: ResMut<BrushState>, mut history: ResMut<History>) {
+pub fn stop_painting(
+    mut brush: ResMut<BrushState>,
+    mut history: ResMut<History>,
+    canvas: Res<Canvas>,
+    images: Res<Assets<Image>>,
+) {
     info!("stopped painting!");
 
-    history.add(HistoryItem::Painted(brush.data.take().unwrap()));
     brush.last_position = None;
+    let (Some(before), Some(target)) = (brush.data.take(), brush.target.take()) else {
+        return;
+    };
+    let Some(after) = images.get(&target.handle).map(|image| &image.data) else {
+        return;
+    };
+    // only the pixels that changed are kept
+    if let Some(delta) = Delta::new(&before, after, canvas.width) {
+        let label = format!("Brush stroke on {}, frame {}", target.name, target.frame);
+        history.add(
+            label,
+            HistoryItem::Painted(target.layer_id, target.frame, delta),
+        );
+    }
 }
 
 pub fn painting(
     mut brush: ResMut<BrushState>,
     canvas: Res<Canvas>,
-    layers: Query<&Layer>,
     mut images: ResMut<Assets<Image>>,
     keyborad: Res<Input<KeyCode>>,
     mut changed_to_pixel: Local<bool>,
@@ -228,7 +260,7 @@ pub fn painting(
         *changed_to_line = true;
 
         brush.clear_buffer(canvas.width, canvas.height);
-        brush.apply_buffer_to_layer(&canvas, &layers, &mut images);
+        brush.apply_buffer_to_layer(&mut images);
 
         brush.mode = BrushMode::Line;
     } else if keyborad.just_released(KeyCode::LShift) {
@@ -247,7 +279,7 @@ pub fn painting(
             let last_pos = if brush.is_pixel_mode() {
                 if *changed_to_pixel {
                     brush.clear_buffer(canvas.width, canvas.height);
-                    brush.apply_buffer_to_layer(&canvas, &layers, &mut images);
+                    brush.apply_buffer_to_layer(&mut images);
                 }
                 last_pos
             } else {
@@ -256,7 +288,7 @@ pub fn painting(
             };
 
             brush.draw_line(canvas.width, last_pos.as_ivec2(), next_pos.as_ivec2());
-            brush.apply_buffer_to_layer(&canvas, &layers, &mut images);
+            brush.apply_buffer_to_layer(&mut images);
             *changed_to_line = false;
             *changed_to_pixel = false;
         }
@@ -269,9 +301,11 @@ pub fn painting(
 pub fn brush_preview(
     mut brush: ResMut<BrushState>,
     canvas: Res<Canvas>,
-    layers: Query<&Layer>,
+    mut frame: ActiveFrame,
     mut images: ResMut<Assets<Image>>,
 ) {
+    // the preview follows the active frame, there's no stroke to hold on to
+    brush.target = Some(frame.target(&mut images));
     if let Ok(pos) = canvas.cursor_position {
         if let Some(last_pos) = brush.last_position {
             if last_pos.as_uvec2() == pos.as_uvec2() {
@@ -279,14 +313,58 @@ pub fn brush_preview(
             }
             brush.clear_buffer(canvas.width, canvas.height);
             brush.draw_point(pos.as_ivec2(), canvas.width);
-            brush.apply_buffer_to_layer(&canvas, &layers, &mut images);
+            brush.apply_buffer_to_layer(&mut images);
         }
         brush.last_position = Some(pos);
     } else {
         if !brush.is_cleared {
             brush.clear_buffer(canvas.width, canvas.height);
-            brush.apply_buffer_to_layer(&canvas, &layers, &mut images);
+            brush.apply_buffer_to_layer(&mut images);
         }
         brush.last_position = None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::rect_mask;
+
+    #[test]
+    fn strokes_stay_in_the_selection() {
+        let (width, height) = (8, 8);
+        let selection = Selection {
+            mask: rect_mask(IVec2::new(2, 2), IVec2::new(5, 5), width, height),
+        };
+        // every pixel is different from the paint
+        let before = (0..width * height)
+            .flat_map(|i| [i as u8, 0, 255 - i as u8, 255])
+            .collect::<Vec<_>>();
+        let mut brush = BrushState {
+            color: Color::rgba_u8(255, 255, 255, 255),
+            data: Some(before.clone()),
+            selection: selection.clone(),
+            ..Default::default()
+        };
+
+        brush.clear_buffer(width, height);
+        brush.draw_line(width, IVec2::new(0, 3), IVec2::new(7, 3));
+        brush.draw_point(IVec2::new(0, 0), width);
+        brush.draw_point(IVec2::new(4, 4), width);
+        let after = brush.get_updated_buffer().unwrap();
+
+        let painted = [(2, 3), (3, 3), (4, 3), (5, 3), (4, 4)];
+        for (i, (a, b)) in before
+            .array_chunks::<4>()
+            .zip(after.array_chunks::<4>())
+            .enumerate()
+        {
+            let pos = (i as u32 % width, i as u32 / width);
+            if painted.contains(&pos) {
+                assert_eq!(*b, [255; 4], "pixel {pos:?} inside the selection");
+            } else {
+                assert_eq!(a, b, "pixel {pos:?} wasn't painted");
+            }
+        }
+    }
+}
@@ -2,7 +2,13 @@ use std::thread;
 
 use rand::Rng;
 
-use crate::{in_img_bounds, History, HistoryItem, ToolState};
+use crate::{
+    delta::Delta,
+    in_img_bounds,
+    layer::{ActiveFrame, Target},
+    selection::Selection,
+    History, HistoryItem, ToolState,
+};
 
 use super::*;
 
@@ -11,6 +17,8 @@ pub struct BucketState {
     data: Option<Vec<u8>>,
     pub fill_color: Color,
     target_color: Color,
+    /// the frame that was filled
+    target: Option<Target>,
 }
 
 impl BucketState {
@@ -50,20 +58,52 @@ impl BucketState {
 
         true
     }
+
+    /// fill the pixels of the target color connected to `start`, the fill stops at the edge
+    /// of the selection.
+    fn fill(
+        &mut self,
+        start: IVec2,
+        width: u32,
+        height: u32,
+        image: &mut [u8],
+        selection: &Selection,
+    ) {
+        flood_fill(start, |pos| {
+            selection.contains(pos, width) && self.span_fill_step(pos, width, height, image)
+        });
+    }
+}
+
+/// walk the pixels connected to `start`, `step` fills a pixel and returns `false` if it
+/// can't be filled or already was, which stops the walk there.
+pub fn flood_fill(start: IVec2, mut step: impl FnMut(IVec2) -> bool) {
+    let mut queue = Vec::from([start]);
+
+    while let Some(pos) = queue.pop() {
+        if step(pos) {
+            queue.push(pos + IVec2::X);
+            queue.push(pos - IVec2::X);
+            queue.push(pos + IVec2::Y);
+            queue.push(pos - IVec2::Y);
+        }
+    }
 }
 
 pub fn start_filling(
     mut bucket: ResMut<BucketState>,
     canvas: Res<Canvas>,
-    layers: Query<&Layer>,
+    mut frame: ActiveFrame,
     mut images: ResMut<Assets<Image>>,
     mut next_state: ResMut<NextState<ToolState>>,
+    selection: Res<Selection>,
 ) {
     info!("started filling!");
 
     if let Ok(pos) = canvas.cursor_position {
-        let layer = layers.get(canvas.layer_id).unwrap();
-        let image = images.get_mut(&layer.frames[&0]).unwrap();
+        let target = frame.target(&mut images);
+        let image = images.get_mut(&target.handle).unwrap();
+        bucket.target = Some(target);
 
         let idx = (pos.y as u32 * canvas.width + pos.x as u32) as usize * 4;
         let color = {
@@ -74,25 +114,40 @@ pub fn start_filling(
         bucket.data = Some(image.data.clone());
 
         // perform fill
-        let mut queue = Vec::from([pos.as_ivec2()]);
-
-        while let Some(pos) = queue.pop() {
-            if bucket.span_fill_step(pos, canvas.width, canvas.height, &mut image.data) {
-                queue.push(pos + IVec2::X);
-                queue.push(pos - IVec2::X);
-                queue.push(pos + IVec2::Y);
-                queue.push(pos - IVec2::Y);
-            }
-        }
+        bucket.fill(
+            pos.as_ivec2(),
+            canvas.width,
+            canvas.height,
+            &mut image.data,
+            &selection,
+        );
     }
 
     next_state.set(ToolState::Idle);
 }
 
-pub fn stop_filling(mut bucket: ResMut<BucketState>, mut history: ResMut<History>) {
+pub fn stop_filling(
+    mut bucket: ResMut<BucketState>,
+    mut history: ResMut<History>,
+    canvas: Res<Canvas>,
+    images: Res<Assets<Image>>,
+) {
     info!("stopped filling!");
 
-    history.add(HistoryItem::Filled(bucket.data.take().unwrap()));
+    // nothing was filled if the click was outside the canvas
+    let (Some(before), Some(target)) = (bucket.data.take(), bucket.target.take()) else {
+        return;
+    };
+    let Some(after) = images.get(&target.handle).map(|image| &image.data) else {
+        return;
+    };
+    if let Some(delta) = Delta::new(&before, after, canvas.width) {
+        let label = format!("Fill on {}, frame {}", target.name, target.frame);
+        history.add(
+            label,
+            HistoryItem::Filled(target.layer_id, target.frame, delta),
+        );
+    }
 }
 
 pub fn filling(
@@ -120,3 +175,41 @@ pub fn filling(
     //     }
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::rect_mask;
+
+    #[test]
+    fn fill_stops_at_the_selection() {
+        let (width, height) = (8, 8);
+        let selection = Selection {
+            mask: rect_mask(IVec2::new(2, 2), IVec2::new(5, 5), width, height),
+        };
+        let before = vec![0; (width * height * 4) as usize];
+        let mut bucket = BucketState {
+            data: None,
+            fill_color: Color::rgba_u8(255, 0, 0, 255),
+            target_color: Color::rgba_u8(0, 0, 0, 0),
+        };
+
+        // starting outside of the selection fills nothing
+        let mut image = before.clone();
+        bucket.fill(IVec2::new(0, 0), width, height, &mut image, &selection);
+        assert_eq!(image, before);
+
+        bucket.fill(IVec2::new(3, 3), width, height, &mut image, &selection);
+        for (i, (a, b)) in before
+            .array_chunks::<4>()
+            .zip(image.array_chunks::<4>())
+            .enumerate()
+        {
+            if selection.contains_index(i) {
+                assert_eq!(*b, [255, 0, 0, 255], "pixel {i} inside the selection");
+            } else {
+                assert_eq!(a, b, "pixel {i} outside the selection");
+            }
+        }
+    }
+}
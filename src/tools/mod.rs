@@ -4,8 +4,9 @@ use crate::{canvas::Canvas, layer::Layer};
 
 pub mod brush;
 pub mod bucket;
+pub mod transform;
 
-pub use {brush::BrushState, bucket::BucketState};
+pub use {brush::BrushState, bucket::BucketState, transform::MoveState};
 
 #[derive(States, Default, Debug, Hash, PartialEq, Eq, Clone)]
 pub enum Tool {
@@ -13,6 +14,8 @@ pub enum Tool {
     Brush,
     Bucket,
     Select,
+    Wand,
+    Move,
 }
 
 #[derive(Default)]
@@ -89,30 +92,17 @@ impl ToolBuffer {
         Some(new)
     }
 
-    pub fn clone_data_from_image(
-        &mut self,
-        canvas: &Res<Canvas>,
-        layers: &Query<&Layer>,
-        images: &mut ResMut<Assets<Image>>,
-    ) {
-        let layer = layers.get(canvas.layer_id).unwrap();
-        let image = images.get_mut(&layer.frames[&0]).unwrap();
+    /// `handle` is picked when the stroke starts, see [`crate::layer::ActiveFrame::target`].
+    pub fn clone_data_from_image(&mut self, handle: &Handle<Image>, images: &Assets<Image>) {
+        let image = images.get(handle).unwrap();
         self.data = Some(image.data.clone());
     }
 
-    pub fn apply_buffer_to_layer(
-        &mut self,
-        canvas: &Res<Canvas>,
-        layers: &Query<&Layer>,
-        images: &mut ResMut<Assets<Image>>,
-    ) {
-        let layer = layers.get(canvas.layer_id).unwrap();
-        let image = images.get_mut(&layer.frames[&0]).unwrap();
+    pub fn apply_buffer_to_layer(&mut self, handle: &Handle<Image>, images: &mut Assets<Image>) {
+        let image = images.get_mut(handle).unwrap();
         if self.data.is_none() {
             self.data = Some(image.data.clone());
         }
-        let layer = layers.get(canvas.layer_id).unwrap();
-        let image = images.get_mut(&layer.frames[&0]).unwrap();
         image.data = self.get_final_image().unwrap();
     }
 }
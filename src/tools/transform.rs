@@ -0,0 +1,280 @@
+use crate::{
+    composite::{blend, BlendMode},
+    delta::Delta,
+    layer::ActiveFrame,
+    selection::Selection,
+    History, HistoryItem,
+};
+
+use super::*;
+
+/// pixels lifted off a frame by the move tool, they're drawn on the frame at `offset`
+/// until they're put down.
+pub struct Floating {
+    layer: Entity,
+    frame: i32,
+    handle: Handle<Image>,
+    /// the size of the canvas when the pixels were lifted
+    width: u32,
+    height: u32,
+    /// what's being moved, like "selection" or "Layer 2"
+    name: String,
+    /// the image before anything was lifted
+    original: Vec<u8>,
+    /// the image with the lifted pixels cut out
+    base: Vec<u8>,
+    /// the lifted pixels, as big as the canvas
+    pixels: Vec<u8>,
+    /// the selection the pixels were lifted from, empty if the whole frame was lifted
+    mask: Vec<u8>,
+    offset: IVec2,
+}
+
+impl Floating {
+    /// cut the selected pixels, or every pixel if nothing is selected, out of `data`.
+    fn lift(
+        layer: Entity,
+        frame: i32,
+        handle: Handle<Image>,
+        (width, height): (u32, u32),
+        name: String,
+        data: &[u8],
+        selection: &Selection,
+    ) -> Self {
+        let mut base = data.to_vec();
+        let mut pixels = vec![0; data.len()];
+        for (i, (b, p)) in base
+            .array_chunks_mut::<4>()
+            .zip(pixels.array_chunks_mut::<4>())
+            .enumerate()
+        {
+            if selection.contains_index(i) {
+                *p = *b;
+                *b = [0; 4];
+            }
+        }
+
+        Floating {
+            layer,
+            frame,
+            handle,
+            width,
+            height,
+            name,
+            original: data.to_vec(),
+            base,
+            pixels,
+            mask: selection.mask.clone(),
+            offset: IVec2::ZERO,
+        }
+    }
+
+    /// the image with the lifted pixels drawn at `offset`.
+    fn render(&self) -> Vec<u8> {
+        let mut data = self.base.clone();
+        let moved = shifted(&self.pixels, self.width, self.height, self.offset, 4);
+        blend(&mut data, &moved, 1.0, BlendMode::Normal);
+        data
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct MoveState {
+    floating: Option<Floating>,
+    /// the cursor and the offset when the drag started
+    drag: Option<(IVec2, IVec2)>,
+}
+
+impl MoveState {
+    pub fn is_floating(&self) -> bool {
+        self.floating.is_some()
+    }
+}
+
+/// move every pixel of `data` (`channels` bytes each) by `offset`, what ends up outside of
+/// the image is cut off.
+pub fn shifted(data: &[u8], width: u32, height: u32, offset: IVec2, channels: usize) -> Vec<u8> {
+    let mut out = vec![0; data.len()];
+    for y in 0..height as i32 {
+        let to_y = y + offset.y;
+        if to_y < 0 || to_y >= height as i32 {
+            continue;
+        }
+        for x in 0..width as i32 {
+            let to_x = x + offset.x;
+            if to_x < 0 || to_x >= width as i32 {
+                continue;
+            }
+            let from = (y as usize * width as usize + x as usize) * channels;
+            let to = (to_y as usize * width as usize + to_x as usize) * channels;
+            out[to..to + channels].copy_from_slice(&data[from..from + channels]);
+        }
+    }
+    out
+}
+
+/// write the moved pixels, and the moved selection, as a single history entry.
+fn put_down(
+    floating: Floating,
+    images: &mut Assets<Image>,
+    selection: &Selection,
+    history: &mut History,
+) {
+    let Some(image) = images.get_mut(&floating.handle) else {
+        return;
+    };
+    if image.data.len() != floating.original.len() {
+        warn!("the image changed size while {} was moved", floating.name);
+        return;
+    }
+    image.data = floating.render();
+
+    let mut items = Vec::new();
+    if let Some(delta) = Delta::new(&floating.original, &image.data, floating.width) {
+        items.push(HistoryItem::Painted(floating.layer, floating.frame, delta));
+    }
+    if !floating.mask.is_empty() && selection.mask != floating.mask {
+        items.push(HistoryItem::Selected(floating.mask));
+    }
+    if items.is_empty() {
+        return;
+    }
+
+    let label = format!(
+        "Move {} by {}, {}",
+        floating.name, floating.offset.x, floating.offset.y
+    );
+    info!("{}", label);
+    history.add(label, HistoryItem::Compound(String::from("move"), items));
+}
+
+/// put the lifted pixels back where they were.
+fn cancel(floating: Floating, images: &mut Assets<Image>, selection: &mut Selection) {
+    match images.get_mut(&floating.handle) {
+        Some(image) if image.data.len() == floating.original.len() => {
+            image.data = floating.original
+        }
+        _ => warn!("the image changed size while {} was moved", floating.name),
+    }
+    if !floating.mask.is_empty() {
+        selection.mask = floating.mask;
+    }
+    info!("cancelled moving {}", floating.name);
+}
+
+/// [left mouse] : drag the selection, or the whole layer if nothing is selected
+/// [arrow keys] : move the selection, or the whole layer, one pixel
+/// [enter] : put the pixels down
+/// [escape] : put the pixels back where they were
+pub fn move_input(
+    input: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    canvas: Res<Canvas>,
+    mut state: ResMut<MoveState>,
+    mut frame: ActiveFrame,
+    mut images: ResMut<Assets<Image>>,
+    mut selection: ResMut<Selection>,
+    mut history: ResMut<History>,
+) {
+    let state = &mut *state;
+
+    // changing the layer or the frame puts the pixels down
+    let moved_away = state.floating.as_ref().map_or(false, |floating| {
+        floating.layer != frame.layer_id() || floating.frame != frame.frame()
+    });
+    // ctrl + enter belongs to the history panel
+    let enter = !input.pressed(KeyCode::LControl) && input.just_pressed(KeyCode::Return);
+    if moved_away || enter {
+        state.drag = None;
+        if let Some(floating) = state.floating.take() {
+            put_down(floating, &mut images, &selection, &mut history);
+        }
+        return;
+    }
+    if input.just_pressed(KeyCode::Escape) {
+        state.drag = None;
+        if let Some(floating) = state.floating.take() {
+            cancel(floating, &mut images, &mut selection);
+        }
+        return;
+    }
+
+    // image rows go down
+    let mut nudge = IVec2::ZERO;
+    if !input.any_pressed([KeyCode::LControl, KeyCode::LAlt]) {
+        for (key, step) in [
+            (KeyCode::Left, -IVec2::X),
+            (KeyCode::Right, IVec2::X),
+            (KeyCode::Up, -IVec2::Y),
+            (KeyCode::Down, IVec2::Y),
+        ] {
+            if input.just_pressed(key) {
+                nudge += step;
+            }
+        }
+    }
+    let start_drag = mouse.just_pressed(MouseButton::Left) && canvas.cursor_on_canvas();
+
+    if state.floating.is_none() && (start_drag || nudge != IVec2::ZERO) {
+        if !frame.layer().is_editable() {
+            warn!("the active layer is hidden or locked");
+            return;
+        }
+        let name = if selection.is_empty() {
+            frame.layer().name.clone()
+        } else {
+            String::from("selection")
+        };
+        let handle = frame.handle(&mut images);
+        let data = &images.get(&handle).unwrap().data;
+        state.floating = Some(Floating::lift(
+            frame.layer_id(),
+            frame.frame(),
+            handle.clone(),
+            (canvas.width, canvas.height),
+            name,
+            data,
+            &selection,
+        ));
+    }
+    let Some(floating) = state.floating.as_mut() else {
+        return;
+    };
+
+    let (Ok(cursor) | Err(cursor)) = canvas.cursor_position;
+    let cursor = cursor.floor().as_ivec2();
+    let mut offset = floating.offset + nudge;
+    if start_drag {
+        state.drag = Some((cursor, offset));
+    }
+    if let Some((start, start_offset)) = state.drag {
+        offset = start_offset + cursor - start;
+    }
+    if mouse.just_released(MouseButton::Left) {
+        state.drag = None;
+    }
+
+    if offset != floating.offset {
+        floating.offset = offset;
+        if let Some(image) = images.get_mut(&floating.handle) {
+            image.data = floating.render();
+        }
+        // the selection moves with the pixels
+        if !floating.mask.is_empty() {
+            selection.mask = shifted(&floating.mask, floating.width, floating.height, offset, 1);
+        }
+    }
+}
+
+/// switching to another tool puts the pixels down.
+pub fn put_down_floating(
+    mut state: ResMut<MoveState>,
+    mut images: ResMut<Assets<Image>>,
+    selection: Res<Selection>,
+    mut history: ResMut<History>,
+) {
+    state.drag = None;
+    if let Some(floating) = state.floating.take() {
+        put_down(floating, &mut images, &selection, &mut history);
+    }
+}